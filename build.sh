@@ -38,3 +38,33 @@ if [ $1 == "extractor" ] || [ $1 == "veda-extractor" ] || [ $1 == "exim" ] || [
     cp $CARGO_TARGET_DIR/release/veda-extractor $VEDA_BIN
 fi
 
+
+if [ $1 == "exim-peer" ] || [ $1 == "veda-exim" ] || [ $1 == "exim" ] || [ -z $1 ]; then
+    echo BUILD VEDA-EXIM
+    rm ./veda-exim
+
+    cd veda-exim
+    cargo build --release
+    cd $BUILD_PATH
+    cp $CARGO_TARGET_DIR/release/veda-exim $VEDA_BIN
+fi
+
+if [ $1 == "exim-bundle" ] || [ $1 == "veda-exim-bundle" ] || [ $1 == "exim" ] || [ -z $1 ]; then
+    echo BUILD VEDA-EXIM-BUNDLE
+    rm ./veda-exim-bundle
+
+    cd veda-exim-bundle
+    cargo build --release
+    cd $BUILD_PATH
+    cp $CARGO_TARGET_DIR/release/veda-exim-bundle $VEDA_BIN
+fi
+
+if [ $1 == "exim-admin" ] || [ $1 == "veda-exim-admin" ] || [ $1 == "exim" ] || [ -z $1 ]; then
+    echo BUILD VEDA-EXIM-ADMIN
+    rm ./veda-exim-admin
+
+    cd veda-exim-admin
+    cargo build --release
+    cd $BUILD_PATH
+    cp $CARGO_TARGET_DIR/release/veda-exim-admin $VEDA_BIN
+fi
@@ -0,0 +1,176 @@
+/*
+ * Асинхронный HTTP клиент обмена с нодой. Клиенты всех нод работают в одном
+ * runtime процесса (shared_runtime), клиент ноды и его пул соединений создаются
+ * один раз на адрес (shared_client). HttpTransport - блокирующая обертка над ним
+ */
+use crate::configuration::Configuration;
+use crate::error::ExImError;
+use crate::IOResult;
+use http::StatusCode;
+use serde_json::json;
+use serde_json::value::Value as JSONValue;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
+
+// запас времени ожидания ответа сверх времени удержания запроса нодой
+const LONG_POLL_TIMEOUT_RESERVE: u64 = 10000;
+
+fn is_empty_message(msg: &JSONValue) -> bool {
+    msg["msg"].as_str().map(|m| m.is_empty()).unwrap_or(true)
+}
+
+// число потоков runtime, в котором выполняются запросы и ожидание всех нод
+const RUNTIME_WORKER_THREADS: usize = 2;
+
+static SHARED_RUNTIME: OnceLock<Runtime> = OnceLock::new();
+static SHARED_CLIENTS: OnceLock<Mutex<HashMap<String, Arc<AsyncHttpClient>>>> = OnceLock::new();
+
+// runtime процесса для обмена с нодами
+pub fn shared_runtime() -> &'static Runtime {
+    SHARED_RUNTIME.get_or_init(|| {
+        Builder::new_multi_thread().worker_threads(RUNTIME_WORKER_THREADS).thread_name("exim-rt").enable_all().build().expect("fail create exim runtime")
+    })
+}
+
+// клиент ноды по адресу, соединения с нодой используются повторно во всех сеансах
+pub fn shared_client(addr: &str) -> Arc<AsyncHttpClient> {
+    let mut clients = SHARED_CLIENTS.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap_or_else(|e| e.into_inner());
+    clients.entry(addr.to_owned()).or_insert_with(|| Arc::new(AsyncHttpClient::new(Configuration::new(addr, "", "")))).clone()
+}
+
+pub struct AsyncHttpClient {
+    resp_api: Configuration,
+    // нода не поддерживает обмен порциями, используются import_delta и export_delta
+    legacy: AtomicBool,
+}
+
+impl AsyncHttpClient {
+    pub fn new(resp_api: Configuration) -> Self {
+        AsyncHttpClient {
+            resp_api,
+            legacy: AtomicBool::new(false),
+        }
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.resp_api.base_path
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.legacy.load(Ordering::Relaxed)
+    }
+
+    fn set_legacy(&self) {
+        warn!("node {} does not support batch exchange, use import_delta/export_delta", self.resp_api.base_path);
+        self.legacy.store(true, Ordering::Relaxed);
+    }
+
+    // идентификатор ноды (node_id), для нод предыдущих версий пустой
+    pub async fn hello(&self) -> Result<String, ExImError> {
+        let uri_str = format!("{}/hello", self.resp_api.base_path);
+        let res = self.resp_api.client.get(&uri_str).send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(String::default());
+        }
+        if res.status() != StatusCode::OK {
+            return Err(ExImError::Transmit(format!("hello: responce status ={}", res.status())));
+        }
+        let info: JSONValue = res.json().await?;
+        Ok(info["node_id"].as_str().unwrap_or_default().to_owned())
+    }
+
+    pub async fn send_message(&self, msg: &JSONValue) -> Result<IOResult, ExImError> {
+        let uri_str = format!("{}/import_delta", self.resp_api.base_path);
+
+        let res = self.resp_api.client.put(&uri_str).json(msg).send().await?;
+
+        if res.status() != StatusCode::OK {
+            error!("responce status ={}", res.status());
+        }
+
+        Ok(res.json().await?)
+    }
+
+    pub async fn recv_message(&self, importer_id: &str, wait: u64) -> Result<JSONValue, ExImError> {
+        if wait > 0 {
+            let uri_str = format!("{}/export_delta/{}?wait={}", self.resp_api.base_path, importer_id, wait);
+            let msg: JSONValue = self.resp_api.client.get(&uri_str).timeout(Duration::from_millis(wait + LONG_POLL_TIMEOUT_RESERVE)).send().await?.json().await?;
+            return Ok(msg);
+        }
+        let uri_str = format!("{}/export_delta/{}", self.resp_api.base_path, importer_id);
+        let msg: JSONValue = self.resp_api.client.get(&uri_str).send().await?.json().await?;
+        Ok(msg)
+    }
+
+    pub async fn send_batch(&self, msgs: &[JSONValue]) -> Result<Vec<IOResult>, ExImError> {
+        if !self.is_legacy() {
+            let uri_str = format!("{}/import_batch", self.resp_api.base_path);
+            let res = self.resp_api.client.put(&uri_str).json(&json!({ "msgs": msgs })).send().await?;
+            if res.status() != StatusCode::NOT_FOUND {
+                if res.status() != StatusCode::OK {
+                    error!("responce status ={}", res.status());
+                }
+                return Ok(res.json().await?);
+            }
+            self.set_legacy();
+        }
+
+        let mut results = vec![];
+        for msg in msgs {
+            results.push(self.send_message(msg).await?);
+        }
+        Ok(results)
+    }
+
+    pub async fn pull_batch(&self, importer_id: &str, max_count: usize, wait: u64) -> Result<Vec<JSONValue>, ExImError> {
+        if !self.is_legacy() {
+            let uri_str = format!("{}/export_batch/{}?max={}&wait={}", self.resp_api.base_path, importer_id, max_count, wait);
+            let res = self.resp_api.client.get(&uri_str).timeout(Duration::from_millis(wait + LONG_POLL_TIMEOUT_RESERVE)).send().await?;
+            if res.status() != StatusCode::NOT_FOUND {
+                let batch: JSONValue = res.json().await?;
+                return Ok(batch["msgs"].as_array().cloned().unwrap_or_default());
+            }
+            self.set_legacy();
+        }
+
+        let msg = self.recv_message(importer_id, wait).await?;
+        if is_empty_message(&msg) {
+            return Ok(vec![]);
+        }
+        Ok(vec![msg])
+    }
+
+    // ожидание изменений у ноды (длинный запрос export_batch): выданная порция остается у ноды до подтверждения.
+    // export_delta фиксирует сообщение при выдаче, поэтому у нод предыдущих версий изменения не ожидаются,
+    // false - ожидание не выполнялось
+    pub async fn wait_changes(&self, importer_id: &str, wait: u64) -> Result<bool, ExImError> {
+        if self.is_legacy() {
+            return Ok(false);
+        }
+
+        let uri_str = format!("{}/export_batch/{}?max=1&wait={}", self.resp_api.base_path, importer_id, wait);
+        let res = self.resp_api.client.get(&uri_str).timeout(Duration::from_millis(wait + LONG_POLL_TIMEOUT_RESERVE)).send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            self.set_legacy();
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    pub async fn ack(&self, importer_id: &str, positions: &[u64]) -> Result<(), ExImError> {
+        // export_delta фиксирует выданное сообщение сразу
+        if self.is_legacy() {
+            return Ok(());
+        }
+
+        let uri_str = format!("{}/ack/{}", self.resp_api.base_path, importer_id);
+        let res = self.resp_api.client.put(&uri_str).json(&json!({ "positions": positions })).send().await?;
+        if res.status() != StatusCode::OK {
+            return Err(ExImError::Transmit(format!("ack: responce status ={}", res.status())));
+        }
+        Ok(())
+    }
+}
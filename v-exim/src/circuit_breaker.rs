@@ -0,0 +1,87 @@
+/*
+ * Автомат защиты обмена с нодой. После exim_circuit_failures сбоев связи подряд
+ * сеансы с нодой прекращаются (Open) на exim_circuit_open_timeout мс, затем
+ * выполняется пробный сеанс (HalfOpen): при успехе обмен возобновляется (Closed),
+ * при сбое автомат снова размыкается
+ */
+use std::time::{Duration, Instant};
+use v_common::module::module_impl::Module;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half-open",
+        }
+    }
+}
+
+pub struct CircuitBreaker {
+    state: CircuitState,
+    failures: u32,
+    threshold: u32,
+    open_timeout: Duration,
+    opened_at: Instant,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, open_timeout: Duration) -> Self {
+        CircuitBreaker {
+            state: CircuitState::Closed,
+            failures: 0,
+            threshold,
+            open_timeout,
+            opened_at: Instant::now(),
+        }
+    }
+
+    pub fn from_properties() -> Self {
+        let threshold = Module::get_property("exim_circuit_failures").unwrap_or_default().parse::<u32>().unwrap_or(5);
+        let open_timeout = Module::get_property("exim_circuit_open_timeout").unwrap_or_default().parse::<u64>().unwrap_or(60000);
+        CircuitBreaker::new(threshold, Duration::from_millis(open_timeout))
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state
+    }
+
+    // можно ли проводить сеанс обмена, по истечении open_timeout автомат переходит в HalfOpen
+    pub fn allow(&mut self) -> bool {
+        if self.state == CircuitState::Open {
+            if self.opened_at.elapsed() < self.open_timeout {
+                return false;
+            }
+            self.state = CircuitState::HalfOpen;
+        }
+        true
+    }
+
+    // время до пробного сеанса
+    pub fn remaining(&self) -> Duration {
+        if self.state != CircuitState::Open {
+            return Duration::ZERO;
+        }
+        self.open_timeout.saturating_sub(self.opened_at.elapsed())
+    }
+
+    pub fn on_success(&mut self) {
+        self.state = CircuitState::Closed;
+        self.failures = 0;
+    }
+
+    pub fn on_failure(&mut self) {
+        self.failures += 1;
+        if self.state == CircuitState::HalfOpen || (self.threshold > 0 && self.failures >= self.threshold) {
+            self.state = CircuitState::Open;
+            self.opened_at = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,209 @@
+/*
+ * Схлопывание повторных изменений одного индивида перед отправкой.
+ * Сообщения вычитываются из очереди окном и сохраняются в файл
+ * ./data/out/pending/<consumer>, из окна передается только последнее
+ * состояние для пары (uri, target), удаления и порядок изменений
+ * одного uri сохраняются. Каждое сообщение окна имеет позицию, по которой
+ * нода подтверждает его прием
+ */
+use base64::{decode, encode};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use v_common::module::module_impl::Module;
+use v_common::onto::individual::{Individual, RawObj};
+use v_common::onto::parser::parse_raw;
+use v_common::v_api::api_client::IndvOp;
+
+const PENDING_PATH: &str = "./data/out/pending";
+
+// размер окна схлопывания, 0 - схлопывание выключено
+pub fn get_coalesce_window() -> usize {
+    Module::get_property("exim_coalesce_window").unwrap_or_default().parse::<usize>().unwrap_or(0)
+}
+
+pub struct PendingWindow {
+    path: String,
+    // позиция следующего сообщения окна, позиции не повторяются и после очистки окна
+    next_pos: u64,
+    msgs: Vec<Vec<u8>>,
+    positions: Vec<u64>,
+}
+
+// файл окна: строка "@<позиция следующего сообщения>", затем строки "<позиция> <сообщение base64>"
+impl PendingWindow {
+    pub fn new(consumer_name: &str) -> Self {
+        let path = format!("{}/{}", PENDING_PATH, consumer_name);
+        // для нового окна отсчет начинается с текущего времени, чтобы позиции не совпали с позициями удаленного окна
+        let mut next_pos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or_default();
+        let mut msgs = vec![];
+        let mut positions = vec![];
+
+        if let Ok(f) = File::open(&path) {
+            for line in BufReader::new(f).lines().map_while(Result::ok) {
+                if line.is_empty() {
+                    continue;
+                }
+                if let Some(pos) = line.strip_prefix('@') {
+                    match pos.parse::<u64>() {
+                        Ok(pos) => next_pos = pos,
+                        Err(e) => error!("pending {}: invalid header {}, err={:?}", path, line, e),
+                    }
+                    continue;
+                }
+                let (pos, data) = match line.split_once(' ') {
+                    Some((pos, data)) => (pos.parse::<u64>().ok(), data),
+                    None => (None, line.as_str()),
+                };
+                match decode(data) {
+                    Ok(m) => {
+                        let pos = pos.unwrap_or(next_pos);
+                        next_pos = next_pos.max(pos + 1);
+                        msgs.push(m);
+                        positions.push(pos);
+                    },
+                    Err(e) => error!("pending {}: fail decode message, err={:?}", path, e),
+                }
+            }
+        }
+
+        PendingWindow {
+            path,
+            next_pos,
+            msgs,
+            positions,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.msgs.is_empty()
+    }
+
+    pub fn messages(&self) -> &[Vec<u8>] {
+        &self.msgs
+    }
+
+    // позиции сообщений окна, в том же порядке, что и messages
+    pub fn positions(&self) -> &[u64] {
+        &self.positions
+    }
+
+    // сообщение записывается в файл до фиксации позиции в очереди
+    pub fn push(&mut self, msg: Vec<u8>) -> std::io::Result<()> {
+        create_dir_all(PENDING_PATH)?;
+        let mut f = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        f.write_all(format!("{} {}\n", self.next_pos, encode(&msg)).as_bytes())?;
+        self.msgs.push(msg);
+        self.positions.push(self.next_pos);
+        self.next_pos += 1;
+        Ok(())
+    }
+
+    // заменяет содержимое окна, сообщения получают новые позиции
+    pub fn store(&mut self, msgs: Vec<Vec<u8>>) -> std::io::Result<()> {
+        let positions = (self.next_pos..self.next_pos + msgs.len() as u64).collect();
+        self.write(msgs, positions)
+    }
+
+    // удаляет из начала окна count сообщений
+    pub fn remove_front(&mut self, count: usize) -> std::io::Result<()> {
+        let count = count.min(self.msgs.len());
+        let msgs = self.msgs[count..].to_vec();
+        let positions = self.positions[count..].to_vec();
+        self.write(msgs, positions)
+    }
+
+    // удаляет сообщения с указанными позициями (подтвержденные нодой), возвращает число удаленных
+    pub fn remove_positions(&mut self, removed: &[u64]) -> std::io::Result<usize> {
+        let keep: Vec<bool> = self.positions.iter().map(|pos| !removed.contains(pos)).collect();
+        let count = keep.iter().filter(|k| !**k).count();
+        if count > 0 {
+            self.retain(&keep)?;
+        }
+        Ok(count)
+    }
+
+    // удаление окна вместе с файлом (потребитель удален или перемещен)
+    pub fn remove(self) -> std::io::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    // схлопывание сохраняет позиции оставшихся сообщений
+    pub fn coalesce(&mut self) -> std::io::Result<()> {
+        let keep = coalesce_keep(&self.msgs);
+        self.retain(&keep)
+    }
+
+    fn retain(&mut self, keep: &[bool]) -> std::io::Result<()> {
+        let mut msgs = vec![];
+        let mut positions = vec![];
+        for ((msg, pos), k) in self.msgs.iter().zip(self.positions.iter()).zip(keep.iter()) {
+            if *k {
+                msgs.push(msg.clone());
+                positions.push(*pos);
+            }
+        }
+        self.write(msgs, positions)
+    }
+
+    fn write(&mut self, msgs: Vec<Vec<u8>>, positions: Vec<u64>) -> std::io::Result<()> {
+        let next_pos = positions.iter().map(|pos| pos + 1).max().unwrap_or(0).max(self.next_pos);
+
+        create_dir_all(PENDING_PATH)?;
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut f = File::create(&tmp_path)?;
+        f.write_all(format!("@{}\n", next_pos).as_bytes())?;
+        for (msg, pos) in msgs.iter().zip(positions.iter()) {
+            f.write_all(format!("{} {}\n", pos, encode(msg)).as_bytes())?;
+        }
+        f.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        self.next_pos = next_pos;
+        self.msgs = msgs;
+        self.positions = positions;
+        Ok(())
+    }
+}
+
+// для каждого сообщения: false - сообщение заменяется следующим за ним состоянием (Put того же uri для тех же
+// адресатов, между ними нет удаления)
+fn coalesce_keep(msgs: &[Vec<u8>]) -> Vec<bool> {
+    let mut superseded: HashMap<(String, String), bool> = HashMap::new();
+    let mut keep = vec![true; msgs.len()];
+
+    for (idx, msg) in msgs.iter().enumerate().rev() {
+        let mut indv = Individual::new_raw(RawObj::new(msg.clone()));
+        if parse_raw(&mut indv).is_err() {
+            continue;
+        }
+
+        let uri = indv.get_first_literal("uri").unwrap_or_default();
+        let target = indv.get_literals("target_veda").unwrap_or_default().join(",");
+        let cmd = if let Some(c) = indv.get_first_integer("cmd") {
+            IndvOp::from_i64(c)
+        } else {
+            continue;
+        };
+
+        let key = (uri, target);
+        if cmd != IndvOp::Remove && *superseded.get(&key).unwrap_or(&false) {
+            keep[idx] = false;
+        }
+
+        if cmd == IndvOp::Put {
+            superseded.insert(key, true);
+        } else if cmd == IndvOp::Remove {
+            superseded.insert(key, false);
+        }
+    }
+
+    let count_out = keep.iter().filter(|k| **k).count();
+    if count_out < msgs.len() {
+        info!("coalesce: {} -> {} messages", msgs.len(), count_out);
+    }
+    keep
+}
@@ -1,13 +1,20 @@
 pub struct Configuration {
     pub base_path: String,
     pub user_agent: Option<String>,
-    pub client: reqwest::blocking::Client,
+    pub client: reqwest::Client,
     pub basic_auth: Option<BasicAuth>,
     pub oauth_access_token: Option<String>,
     pub bearer_access_token: Option<String>,
     pub api_key: Option<ApiKey>,
 }
 
+// то же время ожидания ответа, что у reqwest::blocking::Client по умолчанию
+const REQUEST_TIMEOUT: u64 = 30;
+
+fn create_client() -> reqwest::Client {
+    reqwest::Client::builder().timeout(std::time::Duration::from_secs(REQUEST_TIMEOUT)).build().unwrap_or_default()
+}
+
 pub type BasicAuth = (String, Option<String>);
 
 pub struct ApiKey {
@@ -21,7 +28,7 @@ impl Configuration {
         Configuration {
             base_path: url.to_owned(),
             user_agent: Some("OpenAPI-Generator/771c6a63-9da8-4300-b275-33061d174776/rust".to_owned()),
-            client: reqwest::blocking::Client::new(),
+            client: create_client(),
             basic_auth: Some(ba),
             oauth_access_token: None,
             bearer_access_token: None,
@@ -35,7 +42,7 @@ impl Default for Configuration {
         Configuration {
             base_path: "http://localhost:8734".to_owned(),
             user_agent: Some("OpenAPI-Generator/771c6a63-9da8-4300-b275-33061d174776/rust".to_owned()),
-            client: reqwest::blocking::Client::new(),
+            client: create_client(),
             basic_auth: None,
             oauth_access_token: None,
             bearer_access_token: None,
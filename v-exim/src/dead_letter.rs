@@ -0,0 +1,200 @@
+/*
+ * Очередь недоставленных сообщений (dead letters). Сообщение, которое нода
+ * отклоняет exim_dead_letter_failures раз подряд, либо некорректный элемент
+ * очереди переносится в ./data/out/dead/<consumer>/<id>.json, и передача
+ * продолжается со следующего сообщения. Счетчик отказов хранится в
+ * ./data/out/dead/<consumer>/failures.json. Принятые от ноды, но не записанные
+ * сообщения хранятся в ./data/out/dead/in_<consumer>
+ */
+use crate::ExImCode;
+use serde_json::value::Value as JSONValue;
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read_dir, remove_file, File};
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+use v_common::module::module_impl::Module;
+
+const DEAD_LETTER_PATH: &str = "./data/out/dead";
+const FAILURES_FILE: &str = "failures.json";
+
+// после скольких отказов ноды сообщение переносится в очередь недоставленных, 0 - не переносится
+pub fn get_dead_letter_threshold() -> u32 {
+    Module::get_property("exim_dead_letter_failures").unwrap_or_default().parse::<u32>().unwrap_or(5)
+}
+
+// ключ сообщения для счетчика отказов, сохраняется в failures.json, поэтому хэш не должен зависеть
+// от версии компилятора (DefaultHasher это не гарантирует)
+pub fn dead_letter_key(msg_id: &str, msg: &JSONValue) -> String {
+    format!("{}:{:016x}", msg_id, fnv1a_64(msg.to_string().as_bytes()))
+}
+
+fn fnv1a_64(data: &[u8]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    data.iter().fold(FNV_OFFSET_BASIS, |hash, b| (hash ^ u64::from(*b)).wrapping_mul(FNV_PRIME))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub id: String,
+    pub node_id: String,
+    pub msg_id: String,
+    pub error: ExImCode,
+    pub detail: Option<String>,
+    pub failures: u32,
+    pub date: i64,
+    // сообщение в формате encode_message, для некорректного элемента очереди - сам элемент
+    pub msg: JSONValue,
+    // некорректный элемент очереди, повторная отправка невозможна
+    #[serde(default)]
+    pub invalid: bool,
+    // сообщение принято от ноды, но не записано (очередь in_<consumer>), повтор - повторная запись на этой ноде
+    #[serde(default)]
+    pub received: bool,
+}
+
+impl DeadLetter {
+    pub fn new(node_id: &str, msg_id: &str, error: ExImCode, detail: Option<String>, failures: u32, msg: JSONValue) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or_default();
+        DeadLetter {
+            id: format!("{}_{}", now, Uuid::new_v4().to_simple()),
+            node_id: node_id.to_owned(),
+            msg_id: msg_id.to_owned(),
+            error,
+            detail,
+            failures,
+            date: now / 1000,
+            msg,
+            invalid: false,
+            received: false,
+        }
+    }
+}
+
+pub struct DeadLetterQueue {
+    path: String,
+    pub threshold: u32,
+    failures: HashMap<String, u32>,
+}
+
+impl DeadLetterQueue {
+    pub fn new(consumer_name: &str) -> Self {
+        DeadLetterQueue::with_path(DEAD_LETTER_PATH, consumer_name)
+    }
+
+    pub fn with_path(dir: &str, consumer_name: &str) -> Self {
+        let path = format!("{}/{}", dir, consumer_name);
+        let failures = File::open(format!("{}/{}", path, FAILURES_FILE)).ok().and_then(|f| serde_json::from_reader(f).ok()).unwrap_or_default();
+
+        DeadLetterQueue {
+            path,
+            threshold: get_dead_letter_threshold(),
+            failures,
+        }
+    }
+
+    // потребители, для которых есть недоставленные сообщения
+    pub fn list_consumers() -> Vec<String> {
+        let mut res = vec![];
+        if let Ok(entries) = read_dir(DEAD_LETTER_PATH) {
+            for entry in entries.flatten() {
+                if entry.path().is_dir() {
+                    res.push(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+        }
+        res.sort();
+        res
+    }
+
+    fn store_failures(&self) -> io::Result<()> {
+        create_dir_all(&self.path)?;
+        let tmp_path = format!("{}/{}.tmp", self.path, FAILURES_FILE);
+        File::create(&tmp_path)?.write_all(serde_json::to_string(&self.failures)?.as_bytes())?;
+        std::fs::rename(&tmp_path, format!("{}/{}", self.path, FAILURES_FILE))
+    }
+
+    // возвращает число отказов с учетом текущего
+    pub fn register_failure(&mut self, key: &str) -> u32 {
+        let count = self.failures.entry(key.to_owned()).or_insert(0);
+        *count += 1;
+        let count = *count;
+        if let Err(e) = self.store_failures() {
+            error!("fail store dead letter failures {}, err={:?}", self.path, e);
+        }
+        count
+    }
+
+    pub fn clear_failure(&mut self, key: &str) {
+        if self.failures.remove(key).is_some() {
+            if let Err(e) = self.store_failures() {
+                error!("fail store dead letter failures {}, err={:?}", self.path, e);
+            }
+        }
+    }
+
+    pub fn put(&mut self, key: &str, letter: &DeadLetter) -> io::Result<()> {
+        create_dir_all(&self.path)?;
+        let mut f = File::create(format!("{}/{}.json", self.path, letter.id))?;
+        f.write_all(serde_json::to_string_pretty(letter)?.as_bytes())?;
+        f.sync_all()?;
+        warn!("message {} moved to dead letters {}/{}, err={:?}", letter.msg_id, self.path, letter.id, letter.error);
+        self.clear_failure(key);
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<DeadLetter> {
+        let mut res = vec![];
+        if let Ok(entries) = read_dir(&self.path) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if let Some(id) = name.strip_suffix(".json") {
+                    if name != FAILURES_FILE {
+                        if let Some(letter) = self.get(id) {
+                            res.push(letter);
+                        }
+                    }
+                }
+            }
+        }
+        res.sort_by(|a, b| a.id.cmp(&b.id));
+        res
+    }
+
+    pub fn get(&self, id: &str) -> Option<DeadLetter> {
+        let f = File::open(format!("{}/{}.json", self.path, id)).ok()?;
+        match serde_json::from_reader(f) {
+            Ok(letter) => Some(letter),
+            Err(e) => {
+                error!("fail read dead letter {}/{}, err={:?}", self.path, id, e);
+                None
+            },
+        }
+    }
+
+    pub fn remove(&self, id: &str) -> io::Result<()> {
+        remove_file(format!("{}/{}.json", self.path, id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn fnv1a_64_matches_reference_values() {
+        assert_eq!(fnv1a_64(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a_64(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a_64(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn dead_letter_key_depends_on_message() {
+        let msg = json!({"msg": "AAAA"});
+        assert_eq!(dead_letter_key("d:a", &msg), dead_letter_key("d:a", &msg.clone()));
+        assert_ne!(dead_letter_key("d:a", &msg), dead_letter_key("d:a", &json!({"msg": "BBBB"})));
+    }
+}
@@ -0,0 +1,53 @@
+/*
+ * Вычисление разницы между предыдущим и новым состоянием индивида,
+ * позволяет выгружать только измененные значения предикатов (AddTo/RemoveFrom)
+ */
+use v_common::onto::individual::Individual;
+use v_common::onto::resource::Resource;
+
+// предикаты, которые ведет каждая нода самостоятельно
+const SKIP_PREDICATES: [&str; 1] = ["v-s:updateCounter"];
+
+// возвращает пару (добавленные значения, удаленные значения)
+pub fn get_delta(prev_state: &mut Individual, new_state: &mut Individual) -> (Individual, Individual) {
+    prev_state.parse_all();
+    new_state.parse_all();
+
+    let mut added = Individual::default();
+    added.set_id(new_state.get_id());
+
+    let mut removed = Individual::default();
+    removed.set_id(new_state.get_id());
+
+    let mut predicates = new_state.get_predicates();
+    for predicate in prev_state.get_predicates() {
+        if !predicates.contains(&predicate) {
+            predicates.push(predicate);
+        }
+    }
+
+    for predicate in predicates.iter() {
+        if SKIP_PREDICATES.contains(&predicate.as_str()) {
+            continue;
+        }
+
+        let prev_values = prev_state.get_resources(predicate).unwrap_or_default();
+        let new_values = new_state.get_resources(predicate).unwrap_or_default();
+
+        let add_values: Vec<Resource> = new_values.iter().filter(|r| !prev_values.contains(r)).cloned().collect();
+        if !add_values.is_empty() {
+            added.set_resources(predicate, &add_values);
+        }
+
+        let remove_values: Vec<Resource> = prev_values.iter().filter(|r| !new_values.contains(r)).cloned().collect();
+        if !remove_values.is_empty() {
+            removed.set_resources(predicate, &remove_values);
+        }
+    }
+
+    (added, removed)
+}
+
+pub fn is_empty_delta(delta: &Individual) -> bool {
+    delta.get_predicates().is_empty()
+}
@@ -0,0 +1,66 @@
+/*
+ * Ошибки обработки сообщений обмена. Ноде передается код ExImCode
+ * и текст ошибки (поле detail в IOResult)
+ */
+use crate::ExImCode;
+use std::fmt;
+use std::io;
+use v_common::v_api::obj::ResultCode;
+
+#[derive(Debug)]
+pub enum ExImError {
+    // отсутствует или некорректно поле сообщения
+    InvalidField(&'static str),
+    InvalidCmd(String),
+    InvalidTarget(String),
+    // результат записи индивида (update_use_param)
+    UpdateFailed(ResultCode),
+    Io(io::Error),
+    Transmit(String),
+}
+
+impl ExImError {
+    pub fn code(&self) -> ExImCode {
+        match self {
+            ExImError::InvalidField(_) => ExImCode::InvalidMessage,
+            ExImError::InvalidCmd(_) => ExImCode::InvalidCmd,
+            ExImError::InvalidTarget(_) => ExImCode::InvalidTarget,
+            ExImError::UpdateFailed(_) => ExImCode::FailUpdate,
+            ExImError::Io(_) => ExImCode::FailUpdate,
+            ExImError::Transmit(_) => ExImCode::TransmitFailed,
+        }
+    }
+}
+
+impl fmt::Display for ExImError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExImError::InvalidField(field) => write!(f, "invalid or missing field {}", field),
+            ExImError::InvalidCmd(detail) => write!(f, "invalid cmd: {}", detail),
+            ExImError::InvalidTarget(detail) => write!(f, "invalid target: {}", detail),
+            ExImError::UpdateFailed(rc) => write!(f, "fail update, result_code={:?}", rc),
+            ExImError::Io(e) => write!(f, "i/o error: {}", e),
+            ExImError::Transmit(detail) => write!(f, "fail transmit: {}", detail),
+        }
+    }
+}
+
+impl std::error::Error for ExImError {}
+
+impl From<io::Error> for ExImError {
+    fn from(e: io::Error) -> Self {
+        ExImError::Io(e)
+    }
+}
+
+impl From<ResultCode> for ExImError {
+    fn from(rc: ResultCode) -> Self {
+        ExImError::UpdateFailed(rc)
+    }
+}
+
+impl From<reqwest::Error> for ExImError {
+    fn from(e: reqwest::Error) -> Self {
+        ExImError::Transmit(e.to_string())
+    }
+}
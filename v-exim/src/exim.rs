@@ -4,32 +4,55 @@ extern crate enum_primitive_derive;
 extern crate log;
 extern crate base64;
 
+pub mod async_client;
+pub mod circuit_breaker;
+pub mod coalesce;
 pub mod configuration;
-use crate::configuration::Configuration;
+pub mod dead_letter;
+pub mod delta;
+pub mod error;
+pub mod inquire;
+pub mod linked_node;
+pub mod message;
+pub mod queue_tools;
+pub mod relay;
+pub mod retry;
+pub mod snapshot;
+pub mod status;
+pub mod storage;
+pub mod transport;
+use crate::coalesce::{get_coalesce_window, PendingWindow};
+use crate::dead_letter::{dead_letter_key, DeadLetter, DeadLetterQueue};
+use crate::error::ExImError;
+use crate::linked_node::LinkedNode;
+use crate::message::{ExImMessage, VISITED_PREDICATE};
+use crate::queue_tools::peek_next;
+use crate::retry::RetryPolicy;
+use crate::storage::ImportStorage;
+use crate::transport::Transport;
 
 use base64::{decode, encode};
-use http::StatusCode;
 use num_traits::{FromPrimitive, ToPrimitive};
 use serde_json::json;
 use serde_json::value::Value as JSONValue;
 use std::collections::HashMap;
 use std::error::Error;
-use std::fs::*;
 use std::io::ErrorKind;
-use std::io::Write;
-use std::{thread, time};
+use std::thread;
 use uuid::*;
 use v_common::module::veda_backend::Backend;
 use v_common::onto::datatype::Lang;
 use v_common::onto::individual::{Individual, RawObj};
 use v_common::onto::individual2msgpack::to_msgpack;
 use v_common::onto::parser::parse_raw;
-use v_common::v_api::api_client::{IndvOp, MStorageClient, ALL_MODULES};
+use v_common::v_api::api_client::IndvOp;
 use v_common::v_api::obj::ResultCode;
 use v_queue::consumer::*;
 use v_queue::record::*;
 
 const TRANSMIT_FAILED: i64 = 32;
+// сколько сообщений передается ноде за один запрос
+const SEND_BATCH_SIZE: usize = 100;
 
 #[derive(Primitive, PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 #[repr(i64)]
@@ -81,16 +104,51 @@ impl ExImCode {
         }
         .to_string()
     }
+
+    // сбой связи с нодой (в отличие от отказа ноды в приеме сообщения)
+    pub fn is_transmit_failure(&self) -> bool {
+        i64::from(self.clone()) & TRANSMIT_FAILED != 0
+    }
 }
 
-pub fn send_changes_to_node(queue_consumer: &mut Consumer, resp_api: &Configuration, node_id: &str) -> (i32, ExImCode) {
-    let mut size_batch = 0;
+pub fn send_changes_to_node(queue_consumer: &mut Consumer, transport: &dyn Transport, node_id: &str, relay: bool, policy: &RetryPolicy) -> (i32, ExImCode) {
+    let mut dlq = DeadLetterQueue::new(&queue_consumer.name);
+
+    let coalesce_window = get_coalesce_window();
+    if coalesce_window > 0 {
+        return send_coalesced_changes_to_node(queue_consumer, transport, node_id, relay, policy, coalesce_window, &mut dlq);
+    }
+
+    let mut count_sent = 0;
+    let res = read_queue(queue_consumer, None, &mut |raw| send_queue_element(raw, transport, node_id, relay, policy, &mut dlq, &mut count_sent));
+    (count_sent, res)
+}
+
+// отправка изменений до позиции stop (часть очереди, число прочитанных в ней сообщений), без объединения изменений
+pub fn send_changes_to_node_until(queue_consumer: &mut Consumer, transport: &dyn Transport, node_id: &str, relay: bool, policy: &RetryPolicy, stop: (u32, u32)) -> (i32, ExImCode) {
+    let max_count = if queue_consumer.id < stop.0 {
+        None
+    } else if queue_consumer.id == stop.0 && queue_consumer.count_popped < stop.1 {
+        Some((stop.1 - queue_consumer.count_popped) as usize)
+    } else {
+        return (0, ExImCode::Ok);
+    };
+
+    let mut dlq = DeadLetterQueue::new(&queue_consumer.name);
     let mut count_sent = 0;
+    let res = read_queue(queue_consumer, max_count, &mut |raw| send_queue_element(raw, transport, node_id, relay, policy, &mut dlq, &mut count_sent));
+    (count_sent, res)
+}
+
+// читает из очереди порцию сообщений (не более max_count) и передает их в prepare,
+// позиция в очереди фиксируется только после успешной обработки сообщения
+fn read_queue(queue_consumer: &mut Consumer, max_count: Option<usize>, prepare: &mut dyn FnMut(Vec<u8>) -> ExImCode) -> ExImCode {
+    let mut size_batch = 0;
 
     // read queue current part info
     if let Err(e) = queue_consumer.queue.get_info_of_part(queue_consumer.id, true) {
         error!("get_info_of_part {}: {}", queue_consumer.id, e.as_str());
-        return (count_sent, ExImCode::InvalidMessage);
+        return ExImCode::InvalidMessage;
     }
 
     let delta = queue_consumer.queue.count_pushed - queue_consumer.count_popped;
@@ -114,6 +172,12 @@ pub fn send_changes_to_node(queue_consumer: &mut Consumer, resp_api: &Configurat
         info!("queue: batch size={}", size_batch);
 
         for (total_prepared_count, _it) in (0..size_batch).enumerate() {
+            if let Some(max_count) = max_count {
+                if total_prepared_count >= max_count {
+                    break;
+                }
+            }
+
             // пробуем взять из очереди заголовок сообщения
             if !queue_consumer.pop_header() {
                 break;
@@ -129,41 +193,7 @@ pub fn send_changes_to_node(queue_consumer: &mut Consumer, resp_api: &Configurat
                 break;
             }
 
-            let queue_element = &mut Individual::new_raw(raw);
-
-            let mut res = ExImCode::SendFailed;
-            for attempt_count in 0..10 {
-                let msg = create_export_message(queue_element, node_id);
-
-                match msg {
-                    Ok(mut msg) => {
-                        if let Err(e) = send_export_message(&mut msg, resp_api) {
-                            error!("fail send export message, err={:?}, attempt_count={}", e, attempt_count);
-
-                            if attempt_count >= 9 {
-                                res = ExImCode::SendFailed;
-                                break;
-                            }
-                        } else {
-                            count_sent += 1;
-                            res = ExImCode::Ok;
-                            break;
-                        }
-                    },
-                    Err(e) => {
-                        if e == ExImCode::Ok {
-                            res = e;
-                            break;
-                        }
-                        error!("fail create export message, err={:?}", e);
-                        res = ExImCode::InvalidMessage;
-                        break;
-                    },
-                }
-
-                thread::sleep(time::Duration::from_millis(attempt_count * 100));
-            }
-
+            let res = prepare(raw.data);
             if res == ExImCode::Ok {
                 queue_consumer.commit();
 
@@ -171,76 +201,407 @@ pub fn send_changes_to_node(queue_consumer: &mut Consumer, resp_api: &Configurat
                     info!("get from queue, count: {}", total_prepared_count);
                 }
             } else {
-                return (count_sent, res);
+                return res;
             }
         }
     }
-    (count_sent, ExImCode::Ok)
+    ExImCode::Ok
+}
+
+fn send_queue_element(raw: Vec<u8>, transport: &dyn Transport, node_id: &str, relay: bool, policy: &RetryPolicy, dlq: &mut DeadLetterQueue, count_sent: &mut i32) -> ExImCode {
+    match create_export_message(&mut Individual::new_raw(RawObj::new(raw.clone())), node_id, relay) {
+        Ok(mut out_obj) => match encode_message(&mut out_obj) {
+            Ok(msg) => send_export_batch(&[(out_obj.get_id().to_owned(), msg)], transport, node_id, policy, dlq, count_sent, &mut vec![]),
+            Err(e) => {
+                error!("fail encode out message, err={:?}", e);
+                dead_letter_invalid(dlq, node_id, &raw, ExImCode::InvalidMessage)
+            },
+        },
+        Err(ExImCode::Ok) => ExImCode::Ok,
+        Err(e) => {
+            error!("fail create export message, err={:?}", e);
+            dead_letter_invalid(dlq, node_id, &raw, e)
+        },
+    }
 }
 
-pub fn create_export_message(queue_element: &mut Individual, node_id: &str) -> Result<Individual, ExImCode> {
-    if parse_raw(queue_element).is_ok() {
-        let target_veda = queue_element.get_first_literal("target_veda");
-        if target_veda.is_none() {
-            return Err(ExImCode::InvalidMessage);
+// передает ноде порцию сообщений (id, сообщение), повторяя попытки по политике policy: при сбое связи
+// передается вся порция, при отказе ноды с повторяемым кодом - только отклоненные сообщения.
+// Сообщение, отклоненное с неповторяемым кодом, сразу переносится в очередь недоставленных,
+// с повторяемым - после dlq.threshold сеансов, в которых попытки были исчерпаны.
+// В done добавляются индексы сообщений порции, принятых нодой или перенесенных в очередь недоставленных
+fn send_export_batch(
+    batch: &[(String, JSONValue)],
+    transport: &dyn Transport,
+    node_id: &str,
+    policy: &RetryPolicy,
+    dlq: &mut DeadLetterQueue,
+    count_sent: &mut i32,
+    done: &mut Vec<usize>,
+) -> ExImCode {
+    let mut rest: Vec<(usize, &(String, JSONValue))> = batch.iter().enumerate().collect();
+    let mut rejected: Vec<IOResult> = vec![];
+    let mut attempt_count = 0;
+
+    while !rest.is_empty() && attempt_count < policy.max_attempts {
+        if attempt_count > 0 {
+            thread::sleep(policy.delay(attempt_count - 1));
         }
+        attempt_count += 1;
+
+        let msgs: Vec<JSONValue> = rest.iter().map(|(_, (_, msg))| msg.clone()).collect();
+        let results = match transport.send_batch(&msgs) {
+            Ok(results) if results.len() == msgs.len() => results,
+            Ok(results) => {
+                error!("fail send export batch, err=expected {} results, got {}, attempt_count={}", msgs.len(), results.len(), attempt_count);
+                rejected.clear();
+                continue;
+            },
+            Err(e) => {
+                error!("fail send export batch, err={:?}, attempt_count={}", e, attempt_count);
+                rejected.clear();
+                continue;
+            },
+        };
+
+        let mut retry = vec![];
+        rejected.clear();
+        for (item, r) in rest.into_iter().zip(results.into_iter()) {
+            let (idx, (msg_id, msg)) = item;
+            if r.res_code == ExImCode::Ok {
+                dlq.clear_failure(&dead_letter_key(msg_id, msg));
+                *count_sent += 1;
+                done.push(idx);
+                continue;
+            }
 
-        let target_veda = target_veda.unwrap_or_default();
-        if target_veda != "*" && target_veda != node_id {
-            return Err(ExImCode::Ok);
+            error!("node {} fail accept message, id={}, err={:?}, detail={}", node_id, msg_id, r.res_code, r.detail.as_deref().unwrap_or_default());
+            if policy.is_retryable(&r.res_code) {
+                retry.push(item);
+                rejected.push(r);
+            } else if dead_letter_rejected(dlq, node_id, msg_id, msg, &r, true) != ExImCode::Ok {
+                return r.res_code;
+            } else {
+                done.push(idx);
+            }
         }
+        rest = retry;
+    }
+
+    if rest.is_empty() {
+        info!("sucess send export batch, count={}", batch.len());
+        return ExImCode::Ok;
+    }
 
-        let wcmd = queue_element.get_first_integer("cmd");
-        if wcmd.is_none() {
-            return Err(ExImCode::InvalidMessage);
+    // последняя попытка завершилась сбоем связи
+    if rejected.len() != rest.len() {
+        return ExImCode::SendFailed;
+    }
+
+    let mut res = ExImCode::Ok;
+    for ((idx, (msg_id, msg)), r) in rest.into_iter().zip(rejected.iter()) {
+        if dead_letter_rejected(dlq, node_id, msg_id, msg, r, false) != ExImCode::Ok {
+            res = r.res_code.clone();
+        } else {
+            done.push(idx);
         }
-        let cmd = IndvOp::from_i64(wcmd.unwrap_or_default());
+    }
+    res
+}
 
-        let new_state = queue_element.get_first_binobj("new_state");
-        if cmd != IndvOp::Remove && new_state.is_none() {
-            return Err(ExImCode::InvalidMessage);
+// переносит отклоненное нодой сообщение в очередь недоставленных: сразу (immediately) либо
+// после dlq.threshold отказов, возвращает Ok, если сообщение перенесено
+fn dead_letter_rejected(dlq: &mut DeadLetterQueue, node_id: &str, msg_id: &str, msg: &JSONValue, r: &IOResult, immediately: bool) -> ExImCode {
+    if dlq.threshold == 0 {
+        return r.res_code.clone();
+    }
+
+    let key = dead_letter_key(msg_id, msg);
+    let failures = dlq.register_failure(&key);
+    if !immediately && failures < dlq.threshold {
+        return r.res_code.clone();
+    }
+
+    let letter = DeadLetter::new(node_id, msg_id, r.res_code.clone(), r.detail.clone(), failures, msg.clone());
+    if let Err(e) = dlq.put(&key, &letter) {
+        error!("fail store dead letter, id={}, err={:?}", msg_id, e);
+        return r.res_code.clone();
+    }
+    ExImCode::Ok
+}
+
+// некорректный элемент очереди не будет принят ни при какой попытке, он переносится
+// в очередь недоставленных сразу
+fn dead_letter_invalid(dlq: &mut DeadLetterQueue, node_id: &str, raw: &[u8], code: ExImCode) -> ExImCode {
+    if dlq.threshold == 0 {
+        return code;
+    }
+
+    let mut queue_element = Individual::new_raw(RawObj::new(raw.to_vec()));
+    let msg_id = if parse_raw(&mut queue_element).is_ok() {
+        queue_element.get_id().to_owned()
+    } else {
+        String::default()
+    };
+
+    let msg = json!({ "msg": encode(raw) });
+    let mut letter = DeadLetter::new(node_id, &msg_id, code.clone(), Some("invalid queue element".to_owned()), 1, msg.clone());
+    letter.invalid = true;
+    if let Err(e) = dlq.put(&dead_letter_key(&msg_id, &msg), &letter) {
+        error!("fail store dead letter, id={}, err={:?}", msg_id, e);
+        return code;
+    }
+    ExImCode::Ok
+}
+
+fn send_coalesced_changes_to_node(
+    queue_consumer: &mut Consumer,
+    transport: &dyn Transport,
+    node_id: &str,
+    relay: bool,
+    policy: &RetryPolicy,
+    coalesce_window: usize,
+    dlq: &mut DeadLetterQueue,
+) -> (i32, ExImCode) {
+    let mut pending = PendingWindow::new(&queue_consumer.name);
+
+    // не отправленный ранее остаток окна передается в первую очередь
+    if pending.is_empty() {
+        let res = fill_pending_window(queue_consumer, &mut pending, coalesce_window);
+        if res != ExImCode::Ok {
+            return (0, res);
         }
+    }
 
-        let source_veda = queue_element.get_first_literal("source_veda");
-        if source_veda.is_none() {
-            return Err(ExImCode::InvalidMessage);
+    // некорректный элемент, который не удалось перенести в очередь недоставленных (в том числе при
+    // exim_dead_letter_failures = 0), останавливает передачу, как и без схлопывания: сообщения до него
+    // передаются, он и следующие за ним остаются в окне
+    let mut batch = vec![];
+    let mut positions = vec![];
+    let mut blocked = None;
+    for (raw, pos) in pending.messages().iter().zip(pending.positions()) {
+        let res = match create_export_message(&mut Individual::new_raw(RawObj::new(raw.clone())), node_id, relay) {
+            Ok(mut out_obj) => match encode_message(&mut out_obj) {
+                Ok(msg) => {
+                    batch.push((out_obj.get_id().to_owned(), msg));
+                    positions.push(*pos);
+                    ExImCode::Ok
+                },
+                Err(e) => {
+                    error!("fail encode out message, err={:?}", e);
+                    dead_letter_invalid(dlq, node_id, raw, ExImCode::InvalidMessage)
+                },
+            },
+            Err(ExImCode::Ok) => ExImCode::Ok,
+            Err(e) => {
+                error!("fail create out message {:?}", e);
+                dead_letter_invalid(dlq, node_id, raw, e)
+            },
+        };
+        if res != ExImCode::Ok {
+            blocked = Some((*pos, res));
+            break;
         }
+    }
 
-        let date = queue_element.get_first_integer("date");
-        if date.is_none() {
-            return Err(ExImCode::InvalidMessage);
+    // принятые нодой сообщения удаляются из окна после каждой порции, при сбое повторно передается только остаток
+    let mut count_sent = 0;
+    for (chunk, chunk_positions) in batch.chunks(SEND_BATCH_SIZE).zip(positions.chunks(SEND_BATCH_SIZE)) {
+        let mut done = vec![];
+        let res = send_export_batch(chunk, transport, node_id, policy, dlq, &mut count_sent, &mut done);
+        let done_positions: Vec<u64> = done.iter().map(|idx| chunk_positions[*idx]).collect();
+        if let Err(e) = pending.remove_positions(&done_positions) {
+            error!("fail store pending window, err={:?}", e);
+            return (count_sent, ExImCode::FailUpdate);
         }
+        if res != ExImCode::Ok {
+            return (count_sent, res);
+        }
+    }
 
-        let wid = queue_element.get_first_literal("uri");
-        if wid.is_none() {
-            return Err(ExImCode::InvalidMessage);
+    // переданные сообщения уже удалены из окна, оставшиеся перед некорректным элементом не предназначены ноде
+    if let Some((blocked_pos, res)) = blocked {
+        let count = pending.positions().iter().take_while(|pos| **pos != blocked_pos).count();
+        if let Err(e) = pending.remove_front(count) {
+            error!("fail store pending window, err={:?}", e);
         }
-        let id = wid.unwrap_or_default();
+        return (count_sent, res);
+    }
 
-        let enable_scripts = queue_element.get_first_bool("enable_scripts").unwrap_or(false);
+    if let Err(e) = pending.store(vec![]) {
+        error!("fail store pending window, err={:?}", e);
+    }
+    (count_sent, ExImCode::Ok)
+}
 
-        let mut indv = Individual::new_raw(RawObj::new(new_state.unwrap_or_default()));
-        if parse_raw(&mut indv).is_ok() {
-            indv.parse_all();
+// сообщение для ноды в формате encode_message, Ok(None) - сообщение ноде не передается, Err - некорректный элемент очереди
+fn to_export_json(raw: &[u8], node_id: &str, relay: bool) -> Result<Option<JSONValue>, ExImCode> {
+    match create_export_message(&mut Individual::new_raw(RawObj::new(raw.to_vec())), node_id, relay) {
+        Ok(mut out_obj) => match encode_message(&mut out_obj) {
+            Ok(msg) => Ok(Some(msg)),
+            Err(e) => {
+                error!("fail encode out message, err={:?}", e);
+                Err(ExImCode::InvalidMessage)
+            },
+        },
+        Err(ExImCode::Ok) => Ok(None),
+        Err(e) => {
+            error!("fail create out message {:?}", e);
+            Err(e)
+        },
+    }
+}
+
+// вычитывает из очереди в окно ожидания не более max_count сообщений
+pub fn fill_pending(queue_consumer: &mut Consumer, pending: &mut PendingWindow, max_count: usize) -> ExImCode {
+    read_queue(queue_consumer, Some(max_count), &mut |raw| {
+        if let Err(e) = pending.push(raw) {
+            error!("fail push into pending window, err={:?}", e);
+            return ExImCode::InvalidMessage;
+        }
+        ExImCode::Ok
+    })
+}
 
-            let mut raw: Vec<u8> = Vec::new();
-            if to_msgpack(&indv, &mut raw).is_ok() {
-                let mut new_msg = Individual::default();
-                new_msg.set_id(&format!("{}_{}", cmd.as_string(), &id));
-                new_msg.add_uri("uri", &id);
-                new_msg.add_binary("new_state", raw);
-                new_msg.add_integer("cmd", cmd.to_i64());
-                new_msg.add_integer("date", date.unwrap_or_default());
-                new_msg.add_string("source_veda", &source_veda.unwrap_or_default(), Lang::none());
-                new_msg.add_string("target_veda", &target_veda, Lang::none());
-                new_msg.add_bool("enable_scripts", enable_scripts);
+// вычитывает из очереди окно сообщений и схлопывает повторные изменения
+pub fn fill_pending_window(queue_consumer: &mut Consumer, pending: &mut PendingWindow, coalesce_window: usize) -> ExImCode {
+    let res = fill_pending(queue_consumer, pending, coalesce_window);
 
-                return Ok(new_msg);
+    if let Err(e) = pending.coalesce() {
+        error!("fail store pending window, err={:?}", e);
+        return ExImCode::InvalidMessage;
+    }
+    res
+}
+
+// порция сообщений для ноды node_id (не более max_count) из окна ожидания потребителя, у каждого сообщения
+// позиция в окне (поле pos), окно очищается только после подтверждения приема (ack_pending_export_messages).
+// Сообщения в начале окна, не предназначенные ноде, отбрасываются сразу, некорректные элементы окна переносятся
+// в dead letters потребителя; если перенести не удалось, порция заканчивается перед некорректным элементом
+pub fn get_pending_export_messages(
+    queue_consumer: &mut Consumer,
+    pending: &mut PendingWindow,
+    dlq: &mut DeadLetterQueue,
+    node_id: &str,
+    relay: bool,
+    max_count: usize,
+    coalesce_window: usize,
+) -> Vec<JSONValue> {
+    loop {
+        if pending.is_empty() {
+            let res = if coalesce_window > 0 {
+                fill_pending_window(queue_consumer, pending, coalesce_window)
+            } else {
+                fill_pending(queue_consumer, pending, max_count)
+            };
+            if res != ExImCode::Ok {
+                error!("fail fill pending window {}, err={:?}", queue_consumer.name, res);
+            }
+            if pending.is_empty() {
+                return vec![];
             }
-            // info! ("{:?}", raw);
         }
+
+        let mut msgs = vec![];
+        let mut dropped = vec![];
+        let mut blocked = false;
+        for (raw, pos) in pending.messages().iter().zip(pending.positions()) {
+            if msgs.len() >= max_count {
+                break;
+            }
+            match to_export_json(raw, node_id, relay) {
+                Ok(Some(mut msg)) => {
+                    msg["pos"] = json!(pos);
+                    msgs.push(msg);
+                },
+                Ok(None) => {
+                    if msgs.is_empty() {
+                        dropped.push(*pos);
+                    }
+                },
+                Err(code) => {
+                    if dead_letter_invalid(dlq, node_id, raw, code) != ExImCode::Ok {
+                        blocked = true;
+                        break;
+                    }
+                    dropped.push(*pos);
+                },
+            }
+        }
+
+        if !dropped.is_empty() {
+            if let Err(e) = pending.remove_positions(&dropped) {
+                error!("fail store pending window, err={:?}", e);
+                return vec![];
+            }
+        }
+        if !msgs.is_empty() || blocked || dropped.is_empty() {
+            return msgs;
+        }
+    }
+}
+
+// удаляет из окна ожидания сообщения, прием которых подтвержден нодой (позиции из поля pos).
+// Подтверждение только выданных позиций, поэтому повторное подтверждение той же порции ничего не удаляет
+pub fn ack_pending_export_messages(consumer_name: &str, pending: &mut PendingWindow, positions: &[u64]) -> ExImCode {
+    match pending.remove_positions(positions) {
+        Ok(count) => {
+            if count < positions.len() {
+                warn!("ack {}: {} of {} positions not found in pending window", consumer_name, positions.len() - count, positions.len());
+            }
+            ExImCode::Ok
+        },
+        Err(e) => {
+            error!("fail store pending window, err={:?}", e);
+            ExImCode::FailUpdate
+        },
     }
-    Err(ExImCode::InvalidMessage)
+}
+
+// relay - нода node_id является транзитной, ей передаются сообщения и для других нод
+pub fn create_export_message(queue_element: &mut Individual, node_id: &str, relay: bool) -> Result<Individual, ExImCode> {
+    if parse_raw(queue_element).is_err() {
+        return Err(ExImCode::InvalidMessage);
+    }
+    let mut msg = ExImMessage::from_individual(queue_element).map_err(|e| {
+        error!("invalid queue element {}: {}", queue_element.get_id(), e);
+        e.code()
+    })?;
+
+    // ноды, через которые изменение уже прошло
+    if msg.visited.iter().any(|n| n == node_id) {
+        return Err(ExImCode::Ok);
+    }
+
+    let is_target = is_target_node(&msg.target_veda, node_id, &[]);
+    if !is_target && !relay {
+        return Err(ExImCode::Ok);
+    }
+
+    // получатель видит только свой адрес, транзитная нода - также адресатов, которым она передаст сообщение
+    msg.target_veda = if msg.target_veda.iter().any(|t| t == "*") {
+        vec!["*".to_owned()]
+    } else {
+        let mut targets = vec![];
+        if is_target {
+            targets.push(node_id.to_owned());
+        }
+        if relay {
+            targets.extend(msg.target_veda.iter().filter(|t| t.as_str() != node_id && !msg.visited.contains(t)).cloned());
+        }
+        targets
+    };
+    if msg.target_veda.is_empty() {
+        return Err(ExImCode::Ok);
+    }
+
+    msg.id = format!("{}_{}", msg.cmd.as_string(), msg.uri);
+    Ok(msg.to_individual())
+}
+
+// сообщение адресовано всем нодам ("*"), ноде node_id либо группе нод, в которую она входит (node_groups)
+pub fn is_target_node(target_veda: &[String], node_id: &str, node_groups: &[String]) -> bool {
+    target_veda.iter().any(|t| t == "*" || t == node_id || node_groups.contains(t))
 }
 
 pub fn encode_message(out_obj: &mut Individual) -> Result<JSONValue, Box<dyn Error>> {
@@ -271,25 +632,32 @@ pub fn decode_message(src: &JSONValue) -> Result<Individual, Box<dyn Error>> {
     Err(Box::new(std::io::Error::new(ErrorKind::Other, "fail decode import message".to_owned())))
 }
 
-fn send_export_message(out_obj: &mut Individual, resp_api: &Configuration) -> Result<IOResult, Box<dyn Error>> {
-    let uri_str = format!("{}/import_delta", resp_api.base_path);
-
-    let res = resp_api.client.put(&uri_str).json(&encode_message(out_obj)?).send()?;
-
-    if res.status() != StatusCode::OK {
-        error!("responce status ={}", res.status());
+// есть ли в очереди сообщения, еще не прочитанные потребителем
+pub fn has_new_messages(queue_consumer: &mut Consumer) -> bool {
+    if let Err(e) = queue_consumer.queue.get_info_of_part(queue_consumer.id, true) {
+        error!("get_info_of_part {}: {}", queue_consumer.id, e.as_str());
+        return false;
     }
 
-    let jj: IOResult = res.json()?;
-    info!("sucess send export message: res={:?}, id={}", jj.res_code, out_obj.get_id());
+    if queue_consumer.queue.count_pushed > queue_consumer.count_popped {
+        return true;
+    }
 
-    Ok(jj)
+    // часть очереди прочитана полностью, проверяем не появилась ли следующая
+    queue_consumer.queue.get_info_queue();
+    queue_consumer.queue.id > queue_consumer.id
 }
 
-pub fn recv_import_message(importer_id: &str, resp_api: &Configuration) -> Result<JSONValue, Box<dyn Error>> {
-    let uri_str = format!("{}/export_delta/{}", resp_api.base_path, importer_id);
-    let msg: JSONValue = resp_api.client.get(&uri_str).send()?.json()?;
-    Ok(msg)
+// фиксирует в очереди элементы, не предназначенные ноде node_id, true - следующий элемент требует передачи
+// (в том числе некорректный, он обрабатывается при выдаче), false - новых элементов для ноды нет
+pub fn skip_foreign_messages(queue_consumer: &mut Consumer, node_id: &str, relay: bool) -> bool {
+    while let Some(raw) = peek_next(queue_consumer) {
+        if !matches!(create_export_message(&mut Individual::new_raw(RawObj::new(raw)), node_id, relay), Err(ExImCode::Ok)) {
+            return true;
+        }
+        queue_consumer.commit();
+    }
+    false
 }
 
 #[macro_use]
@@ -299,6 +667,9 @@ extern crate serde_derive;
 pub struct IOResult {
     pub id: String,
     pub res_code: ExImCode,
+    // текст ошибки, ноды предыдущих версий его не передают и не учитывают
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
 }
 
 impl IOResult {
@@ -306,129 +677,212 @@ impl IOResult {
         IOResult {
             id: id.to_owned(),
             res_code,
+            detail: None,
         }
     }
-}
 
-pub fn processing_imported_message(my_node_id: &str, recv_msg: &mut Individual, systicket: &str, mstorage_client: &mut MStorageClient) -> IOResult {
-    let wcmd = recv_msg.get_first_integer("cmd");
-    if wcmd.is_none() {
-        return IOResult::new(recv_msg.get_id(), ExImCode::InvalidCmd);
+    pub fn from_error(id: &str, err: &ExImError) -> Self {
+        IOResult {
+            id: id.to_owned(),
+            res_code: err.code(),
+            detail: Some(err.to_string()),
+        }
     }
-    let cmd = IndvOp::from_i64(wcmd.unwrap_or_default());
+}
 
-    let source_veda = recv_msg.get_first_literal("source_veda");
-    if source_veda.is_none() {
-        return IOResult::new(recv_msg.get_id(), ExImCode::InvalidTarget);
+// my_groups - группы нод, в которые входит эта нода (get_node_group_membership)
+pub fn processing_imported_message(my_node_id: &str, my_groups: &[String], recv_msg: &mut Individual, systicket: &str, storage: &mut dyn ImportStorage) -> IOResult {
+    let msg = match ExImMessage::from_individual(recv_msg) {
+        Ok(msg) => msg,
+        Err(e) => {
+            error!("invalid message {}: {}", recv_msg.get_id(), e);
+            return IOResult::from_error(recv_msg.get_id(), &e);
+        },
+    };
+
+    // ноды, через которые изменение уже прошло, повторно изменение не принимается
+    if msg.visited.iter().any(|n| n == my_node_id) {
+        info!("skip {}, node {} already visited", recv_msg.get_id(), my_node_id);
+        return IOResult::new(recv_msg.get_id(), ExImCode::Ok);
     }
-
-    let source_veda = source_veda.unwrap_or_default();
-    if source_veda.len() < 32 {
-        return IOResult::new(recv_msg.get_id(), ExImCode::InvalidTarget);
+    let mut visited = msg.visited.clone();
+    for node_id in [msg.source_veda.clone(), my_node_id.to_owned()] {
+        if !visited.contains(&node_id) {
+            visited.push(node_id);
+        }
     }
 
-    let target_veda = recv_msg.get_first_literal("target_veda");
-    if target_veda.is_none() {
-        return IOResult::new(recv_msg.get_id(), ExImCode::InvalidTarget);
-    }
-    let target_veda = target_veda.unwrap();
+    // адресаты, для которых эта нода является транзитной
+    let relay_targets: Vec<String> = msg.target_veda.iter().filter(|t| t.as_str() != "*" && !visited.contains(t) && !my_groups.contains(t)).cloned().collect();
+    let is_target = is_target_node(&msg.target_veda, my_node_id, my_groups);
 
-    if target_veda != "*" && my_node_id != target_veda {
-        return IOResult::new(recv_msg.get_id(), ExImCode::InvalidTarget);
+    if !is_target && relay_targets.is_empty() {
+        return IOResult::from_error(recv_msg.get_id(), &ExImError::InvalidTarget(format!("node {} is not in target_veda {:?}", my_node_id, msg.target_veda)));
     }
 
-    let enable_scripts = recv_msg.get_first_bool("enable_scripts").unwrap_or(false);
-
-    let new_state = recv_msg.get_first_binobj("new_state");
-
-    let mut indv = Individual::new_raw(RawObj::new(new_state.unwrap_or_default()));
-    if parse_raw(&mut indv).is_ok() {
-        indv.parse_all();
+    // сообщение пересылается без изменений (любая команда), заменяются только адресаты и маршрут
+    let relay_msg = if relay_targets.is_empty() {
+        None
+    } else {
+        Some(ExImMessage {
+            target_veda: relay_targets,
+            visited: visited.clone(),
+            ..msg.clone()
+        })
+    };
+
+    // транзитная нода не сохраняет данные, адресованные другим нодам
+    if !is_target {
+        return relay_message(recv_msg.get_id(), relay_msg, storage);
+    }
 
+    // удаление может передаваться без состояния индивида
+    let mut indv = msg.get_new_state().unwrap_or_default();
+    let ExImMessage {
+        uri,
+        cmd,
+        source_veda,
+        enable_scripts,
+        ..
+    } = msg;
+
+    // патч RemoveFrom удалил бы sys:source у принимающей стороны
+    if cmd != IndvOp::RemoveFrom {
         if indv.any_exists("sys:source", &[my_node_id]) {
             indv.remove("sys:source");
         } else {
             indv.add_uri("sys:source", &source_veda);
         }
+    }
 
-        if cmd == IndvOp::Remove {
-            if let Some(id) = recv_msg.get_first_literal("uri") {
-                indv.set_id(&id);
-            }
-        }
+    if cmd == IndvOp::Remove {
+        indv.set_id(&uri);
+    }
 
-        if indv.any_exists("rdf:type", &["v-s:File"]) {
-            if let Some(file_data) = indv.get_first_binobj("v-s:fileData") {
-                let src_dir_path = "data/files".to_owned() + &indv.get_first_literal("v-s:filePath").unwrap_or_default();
+    // маршрут сохраняется вместе с состоянием индивида: изменения, сделанные скриптами при его записи,
+    // veda-extractor не возвращает нодам маршрута. Патчи дополняли бы прежний маршрут, он не сохраняется
+    if cmd == IndvOp::Put {
+        indv.remove(VISITED_PREDICATE);
+        for node_id in visited.iter() {
+            indv.add_uri(VISITED_PREDICATE, node_id);
+        }
+    }
 
-                if let Err(e) = create_dir_all(src_dir_path.clone()) {
-                    error!("fail create path: {:?}", e);
-                }
+    if indv.any_exists("rdf:type", &["v-s:File"]) {
+        if let Some(file_data) = indv.get_first_binobj("v-s:fileData") {
+            let dir_path = indv.get_first_literal("v-s:filePath").unwrap_or_default();
+            let file_name = indv.get_first_literal("v-s:fileUri").unwrap_or_default();
 
-                let src_full_path = src_dir_path + "/" + &indv.get_first_literal("v-s:fileUri").unwrap_or_default();
-
-                match File::create(src_full_path.clone()) {
-                    Ok(mut ofile) => {
-                        if let Err(e) = ofile.write_all(&file_data) {
-                            error!("fail write file: {:?}", e);
-                        } else {
-                            info!("success create file {}", src_full_path);
-                        }
-                    },
-                    Err(e) => {
-                        error!("fail create file: {:?}", e);
-                    },
-                }
-                indv.remove("v-s:fileData");
+            // без содержимого файла индивид не записывается, нода передаст сообщение повторно
+            if let Err(e) = storage.store_file(&dir_path, &file_name, &file_data) {
+                error!("fail store file {}/{}: {:?}", dir_path, file_name, e);
+                return IOResult::from_error(recv_msg.get_id(), &ExImError::Io(e));
             }
+            indv.remove("v-s:fileData");
         }
+    }
 
-        let src = if enable_scripts {
-            "?"
-        } else {
-            "exim"
-        };
+    let src = if enable_scripts {
+        "?"
+    } else {
+        "exim"
+    };
+
+    match storage.update_individual(systicket, cmd, &indv) {
+        Ok(_) => {
+            info!("get from {}, success update, src={}, uri={}", source_veda, src, recv_msg.get_id());
+            relay_message(recv_msg.get_id(), relay_msg, storage)
+        },
+        Err(rc) => {
+            let e = ExImError::from(rc);
+            error!("uri={}: {}", recv_msg.get_id(), e);
+            IOResult::from_error(recv_msg.get_id(), &e)
+        },
+    }
+}
 
-        match mstorage_client.update_use_param(systicket, "exim", "?", ALL_MODULES, cmd, &indv) {
-            Ok(_) => {
-                info!("get from {}, success update, src={}, uri={}", source_veda, src, recv_msg.get_id());
-                return IOResult::new(recv_msg.get_id(), ExImCode::Ok);
-            },
-            Err(e) => {
-                error!("fail update, uri={}, result_code={:?}", recv_msg.get_id(), e.result);
-                return IOResult::new(recv_msg.get_id(), ExImCode::FailUpdate);
-            },
+fn relay_message(msg_id: &str, relay_msg: Option<ExImMessage>, storage: &mut dyn ImportStorage) -> IOResult {
+    if let Some(relay_msg) = relay_msg {
+        if let Err(e) = storage.relay_message(&relay_msg) {
+            error!("fail relay {}: {:?}", msg_id, e);
+            return IOResult::from_error(msg_id, &ExImError::Io(e));
         }
+        info!("relay {} to {:?}", msg_id, relay_msg.target_veda);
     }
-
-    IOResult::new(recv_msg.get_id(), ExImCode::FailUpdate)
+    IOResult::new(msg_id, ExImCode::Ok)
 }
 
-pub fn load_linked_nodes(backend: &mut Backend, node_upd_counter: &mut i64, link_node_addresses: &mut HashMap<String, String>) {
+pub fn load_linked_nodes(backend: &mut Backend, node_upd_counter: &mut i64, link_node_addresses: &mut HashMap<String, LinkedNode>) {
     let mut node = Individual::default();
 
     if backend.storage.get_individual("cfg:standart_node", &mut node) {
         if let Some(c) = node.get_first_integer("v-s:updateCounter") {
             if c > *node_upd_counter {
                 link_node_addresses.clear();
-                if let Some(v) = node.get_literals("cfg:linked_node") {
-                    for el in v {
-                        let mut link_node = Individual::default();
-
-                        if backend.storage.get_individual(&el, &mut link_node) && !link_node.is_exists("v-s:delete") {
-                            if let Some(addr) = link_node.get_first_literal("rdf:value") {
-                                link_node_addresses.insert(link_node.get_first_literal("cfg:node_id").unwrap_or_default(), addr);
-                            }
-                        }
+                for mut link_node in get_linked_nodes(backend) {
+                    if let Some(n) = LinkedNode::new(&mut link_node) {
+                        link_node_addresses.insert(n.node_id.clone(), n);
                     }
-                    info!("linked nodes: {:?}", link_node_addresses);
                 }
+                info!("linked nodes: {:?}", link_node_addresses);
                 *node_upd_counter = c;
             }
         }
     }
 }
 
+// читает описания связанных нод (v-s:LinkedNode) из cfg:standart_node
+pub fn get_linked_nodes(backend: &mut Backend) -> Vec<Individual> {
+    let mut res = vec![];
+    let mut node = Individual::default();
+
+    if backend.storage.get_individual("cfg:standart_node", &mut node) {
+        if let Some(v) = node.get_literals("cfg:linked_node") {
+            for el in v {
+                let mut link_node = Individual::default();
+
+                if backend.storage.get_individual(&el, &mut link_node) && !link_node.is_exists("v-s:delete") {
+                    res.push(link_node);
+                }
+            }
+        }
+    }
+    res
+}
+
+// группы нод (v-s:LinkedNodeGroup) из cfg:standart_node: id группы -> node_id участников
+pub fn get_node_groups(backend: &mut Backend) -> HashMap<String, Vec<String>> {
+    let mut groups = HashMap::new();
+    let mut node = Individual::default();
+
+    if backend.storage.get_individual("cfg:standart_node", &mut node) {
+        for group_id in node.get_literals("cfg:linked_node_group").unwrap_or_default() {
+            let mut group = Individual::default();
+            if !backend.storage.get_individual(&group_id, &mut group) || group.is_exists("v-s:delete") {
+                continue;
+            }
+
+            let mut members = vec![];
+            for el in group.get_literals("cfg:linked_node").unwrap_or_default() {
+                let mut link_node = Individual::default();
+                if backend.storage.get_individual(&el, &mut link_node) && !link_node.is_exists("v-s:delete") {
+                    if let Some(node_id) = link_node.get_first_literal("cfg:node_id") {
+                        members.push(node_id);
+                    }
+                }
+            }
+            groups.insert(group_id, members);
+        }
+    }
+    info!("linked node groups: {:?}", groups);
+    groups
+}
+
+// группы нод, в описании которых указана нода node_id
+pub fn get_node_group_membership(backend: &mut Backend, node_id: &str) -> Vec<String> {
+    get_node_groups(backend).into_iter().filter(|(_, members)| members.iter().any(|m| m == node_id)).map(|(group_id, _)| group_id).collect()
+}
+
 pub fn get_db_id(backend: &mut Backend) -> Option<String> {
     let mut indv = Individual::default();
     if backend.storage.get_individual("cfg:system", &mut indv) {
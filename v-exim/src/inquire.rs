@@ -0,0 +1,801 @@
+/*
+ * Сеансы обмена со связанными нодами: отправка накопленных в очереди
+ * ./data/out/extract изменений (потребитель i_<node>) и запрос изменений
+ * у ноды. Используется veda-exim-inquire, veda-exim и veda-exim-respond
+ * (связанные ноды в режиме push).
+ * Задачи нод работают в общем runtime, сеансы выполняются в пуле потоков
+ */
+use crate::async_client::{shared_client, shared_runtime};
+use crate::circuit_breaker::{CircuitBreaker, CircuitState};
+use crate::dead_letter::{dead_letter_key, DeadLetter, DeadLetterQueue};
+use crate::linked_node::{ExchangeMode, LinkedNode};
+use crate::message::get_sender;
+use crate::queue_tools::open_node_consumer;
+use crate::retry::{IdlePolicy, RetryPolicy};
+use crate::status::{get_queue_lag, NodeStatus, StatusModule};
+use crate::storage::ImportStorage;
+use crate::transport::{create_transport, Transport};
+use crate::{decode_message, get_node_group_membership, load_linked_nodes, processing_imported_message, send_changes_to_node, ExImCode, IOResult};
+use serde_json::value::Value as JSONValue;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{create_dir_all, remove_file, File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Instant;
+use std::{thread, time};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use v_common::module::module_impl::Module;
+use v_common::module::veda_backend::Backend;
+use v_common::storage::common::StorageMode;
+
+// время (мс), на которое запрос изменений может быть удержан нодой до появления новых данных,
+// 0 - длинные запросы не используются
+pub fn get_long_poll_wait() -> u64 {
+    Module::get_property("exim_long_poll_wait").unwrap_or_default().parse::<u64>().unwrap_or(0)
+}
+
+// как часто проверяются изменения описаний связанных нод (мс)
+const LINKED_NODES_CHECK_INTERVAL: u64 = 1000;
+const DEFAULT_MAX_PARALLEL_SESSIONS: usize = 4;
+
+// результат сеанса обмена с нодой
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    // отправка и запрос изменений завершились без ошибок
+    pub pushed: bool,
+    pub pulled: bool,
+    pub count_sent: i32,
+    pub count_recv: i32,
+    pub queue_lag: Option<i64>,
+    // код последней ошибки сеанса
+    pub error: Option<ExImCode>,
+}
+
+impl SyncReport {
+    pub fn is_active(&self) -> bool {
+        self.count_sent > 0 || self.count_recv > 0
+    }
+
+    // сбой связи с нодой
+    pub fn is_failed(&self) -> bool {
+        self.error.as_ref().map(|e| e.is_transmit_failure()).unwrap_or(false)
+    }
+}
+
+struct NodeWorker {
+    node: LinkedNode,
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+// сеанс обмена выполняется в потоке пула со своим Backend (Backend нельзя передавать между потоками)
+type SessionJob = Box<dyn FnOnce(&mut Backend) + Send>;
+
+// потоки, в которых выполняются сеансы обмена (чтение очереди, запись принятых изменений),
+// одновременно выполняется не более exim_max_parallel_sessions сеансов
+#[derive(Clone)]
+struct SessionPool {
+    sender: mpsc::Sender<SessionJob>,
+}
+
+impl SessionPool {
+    fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<SessionJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for idx in 0..size.max(1) {
+            let receiver = receiver.clone();
+            let spawned = thread::Builder::new().name(format!("exim-session-{}", idx)).spawn(move || {
+                let mut backend = Backend::create(StorageMode::ReadOnly, false);
+                loop {
+                    let job = match receiver.lock() {
+                        Ok(r) => r.recv(),
+                        Err(_) => break,
+                    };
+                    match job {
+                        Ok(job) => {
+                            if panic::catch_unwind(AssertUnwindSafe(|| job(&mut backend))).is_err() {
+                                error!("exchange session terminated abnormally");
+                            }
+                        },
+                        Err(_) => break,
+                    }
+                }
+            });
+            if let Err(e) = spawned {
+                error!("fail start exchange session thread, err={:?}", e);
+            }
+        }
+
+        SessionPool {
+            sender,
+        }
+    }
+
+    // результат выполнения f в потоке пула, ошибка - пул остановлен или сеанс завершился аварийно
+    fn submit<T, F>(&self, f: F) -> oneshot::Receiver<T>
+    where
+        F: FnOnce(&mut Backend) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: SessionJob = Box::new(move |backend| {
+            let _ = tx.send(f(backend));
+        });
+        if self.sender.send(job).is_err() {
+            error!("exchange session pool is stopped");
+        }
+        rx
+    }
+}
+
+// сколько сеансов обмена выполняется одновременно
+fn get_max_parallel_sessions() -> usize {
+    Module::get_property("exim_max_parallel_sessions").unwrap_or_default().parse::<usize>().unwrap_or(DEFAULT_MAX_PARALLEL_SESSIONS)
+}
+
+// modes - режимы обмена связанных нод, с которыми проводятся сеансы.
+// Обмен с каждой нодой ведет отдельная задача в общем runtime (shared_runtime) со своей паузой и автоматом
+// защиты, поэтому недоступная нода не задерживает обмен с остальными. Паузы и ожидание изменений у ноды
+// (длинный запрос) не занимают потоков, сеансы выполняются в пуле потоков ограниченного размера
+pub fn inquire_linked_nodes(backend: &mut Backend, my_node_id: &str, sys_ticket: &str, modes: &[ExchangeMode]) {
+    // загрузка адресов связанных нод
+    let mut node_upd_counter = 0;
+    let mut link_node_addresses = HashMap::new();
+
+    let long_poll_wait = get_long_poll_wait();
+    info!("long poll wait={} ms", long_poll_wait);
+
+    let max_sessions = get_max_parallel_sessions();
+    info!("max parallel sessions={}", max_sessions);
+    let pool = SessionPool::new(max_sessions);
+
+    let mut workers: HashMap<String, NodeWorker> = HashMap::new();
+    // остановленные задачи, еще не завершившие текущий сеанс (повторы отправки, длинный запрос)
+    let mut stopping: HashMap<String, NodeWorker> = HashMap::new();
+
+    loop {
+        load_linked_nodes(backend, &mut node_upd_counter, &mut link_node_addresses);
+
+        // обмен с удаленными и измененными нодами останавливается без ожидания завершения задачи,
+        // чтобы не задерживать обмен с остальными нодами
+        let changed: Vec<String> = workers
+            .iter()
+            .filter(|(node_id, w)| link_node_addresses.get(*node_id) != Some(&w.node))
+            .map(|(node_id, _)| node_id.clone())
+            .collect();
+        for node_id in changed {
+            if let Some(w) = workers.remove(&node_id) {
+                info!("stop exchange with node {}", node_id);
+                w.stop.store(true, Ordering::Relaxed);
+                stopping.insert(node_id, w);
+            }
+        }
+
+        reap_stopped_workers(&mut stopping);
+
+        // новая задача для ноды запускается после завершения прежней, чтобы потребитель очереди не использовался одновременно
+        for remote_node in link_node_addresses.values().filter(|n| modes.contains(&n.mode)) {
+            if !workers.contains_key(&remote_node.node_id) && !stopping.contains_key(&remote_node.node_id) {
+                workers.insert(remote_node.node_id.clone(), start_node_worker(&pool, remote_node, my_node_id, sys_ticket, long_poll_wait));
+            }
+        }
+
+        thread::sleep(time::Duration::from_millis(LINKED_NODES_CHECK_INTERVAL));
+    }
+}
+
+// завершившиеся задачи остановленных нод
+fn reap_stopped_workers(stopping: &mut HashMap<String, NodeWorker>) {
+    let finished: Vec<String> = stopping.iter().filter(|(_, w)| w.handle.is_finished()).map(|(node_id, _)| node_id.clone()).collect();
+    for node_id in finished {
+        if let Some(w) = stopping.remove(&node_id) {
+            if shared_runtime().block_on(w.handle).is_err() {
+                error!("exchange with node {} terminated abnormally", node_id);
+            }
+        }
+    }
+}
+
+fn start_node_worker(pool: &SessionPool, remote_node: &LinkedNode, my_node_id: &str, sys_ticket: &str, long_poll_wait: u64) -> NodeWorker {
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let handle = shared_runtime().spawn(node_worker(pool.clone(), my_node_id.to_owned(), sys_ticket.to_owned(), remote_node.clone(), long_poll_wait, stop.clone()));
+
+    NodeWorker {
+        node: remote_node.clone(),
+        stop,
+        handle,
+    }
+}
+
+async fn node_worker(pool: SessionPool, my_node_id: String, sys_ticket: String, remote_node: LinkedNode, long_poll_wait: u64, stop: Arc<AtomicBool>) {
+    info!("start exchange with node {}, mode={:?}", remote_node.node_id, remote_node.mode);
+
+    let mut breaker = CircuitBreaker::from_properties();
+    let (link_node_id, node_id) = (remote_node.id.clone(), remote_node.node_id.clone());
+    let mut status = match pool.submit(move |backend| NodeStatus::load(backend, StatusModule::Inquire, &link_node_id, &node_id)).await {
+        Ok(status) => status,
+        Err(_) => return,
+    };
+    // пауза между сеансами без изменений и после сбоев увеличивается, пока нет изменений
+    let idle_policy = IdlePolicy::from_properties();
+    let mut idle_rounds = 0;
+
+    // изменения у ноды ожидаются длинным запросом общего HTTP клиента, сеанс при этом не занимает поток пула.
+    // Запросы NNG нода обслуживает последовательно, поэтому для них ожидание не используется
+    let pulls = remote_node.mode == ExchangeMode::Inquire || remote_node.mode == ExchangeMode::Push;
+    let waiter = if long_poll_wait > 0 && pulls && !remote_node.addr.starts_with("tcp://") {
+        Some(shared_client(&remote_node.addr))
+    } else {
+        None
+    };
+    let mut wait_changes = false;
+
+    while !stop.load(Ordering::Relaxed) {
+        if !breaker.allow() {
+            sleep_while_running(&stop, breaker.remaining()).await;
+            continue;
+        }
+
+        let round_start = Instant::now();
+
+        // после сеанса без изменений ждем их появления у ноды; выданное сообщение остается у нее
+        // до подтверждения и будет получено сеансом. Нода предыдущей версии (export_delta) не ожидается
+        let mut waited = false;
+        if let (true, Some(client)) = (wait_changes, &waiter) {
+            waited = match client.wait_changes(&my_node_id, long_poll_wait).await {
+                Ok(waited) => waited,
+                Err(e) => {
+                    warn!("wait changes from {}: {}", remote_node.node_id, e);
+                    true
+                },
+            };
+        }
+
+        // ожидание уже выполнено, сеанс забирает накопленное без удержания запроса
+        let (node, my_id, ticket) = (remote_node.clone(), my_node_id.clone(), sys_ticket.clone());
+        let report = match pool.submit(move |backend| sync_with_node(backend, &my_id, &ticket, &node, 0)).await {
+            Ok(report) => report,
+            Err(_) => break,
+        };
+
+        if report.is_failed() {
+            breaker.on_failure();
+            if breaker.state() == CircuitState::Open {
+                warn!("node {} is unavailable, exchange suspended for {:?}", remote_node.node_id, breaker.remaining());
+            }
+        } else {
+            breaker.on_success();
+        }
+        update_status(&mut status, &report, breaker.state());
+        let ticket = sys_ticket.clone();
+        status = match pool
+            .submit(move |backend| {
+                status.store_if_needed(&mut backend.mstorage_api, &ticket);
+                status
+            })
+            .await
+        {
+            Ok(status) => status,
+            Err(_) => break,
+        };
+
+        if report.is_active() {
+            idle_rounds = 0;
+        }
+        wait_changes = !report.is_active() && !report.is_failed();
+
+        // ожидание новых изменений выполняется длинным запросом в начале следующего сеанса,
+        // пауза нужна, только если нода ответила на него раньше срока без изменений
+        let long_poll = waiter.as_ref().map(|client| !client.is_legacy()).unwrap_or(false);
+        if long_poll && !report.is_failed() && (report.is_active() || !waited || round_start.elapsed() >= time::Duration::from_millis(long_poll_wait)) {
+            continue;
+        }
+
+        sleep_while_running(&stop, idle_policy.delay(idle_rounds)).await;
+        idle_rounds += 1;
+    }
+    info!("exchange with node {} stopped", remote_node.node_id);
+}
+
+fn update_status(status: &mut NodeStatus, report: &SyncReport, circuit_state: CircuitState) {
+    if report.pushed {
+        status.on_push(report.count_sent);
+    }
+    if report.pulled {
+        status.on_pull(report.count_recv);
+    }
+    if let Some(lag) = report.queue_lag {
+        status.set_queue_lag(lag);
+    }
+    if let Some(e) = &report.error {
+        status.on_error(e.clone());
+    }
+    status.set_circuit_state(circuit_state);
+}
+
+// пауза, прерываемая остановкой обмена
+async fn sleep_while_running(stop: &AtomicBool, duration: time::Duration) {
+    let deadline = Instant::now() + duration;
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        tokio::time::sleep((deadline - now).min(time::Duration::from_millis(LINKED_NODES_CHECK_INTERVAL))).await;
+    }
+}
+
+// сколько сообщений запрашивается у ноды за один запрос
+const PULL_BATCH_SIZE: usize = 100;
+const APPLIED_PATH: &str = "./data/out/applied";
+
+pub fn sync_with_node(backend: &mut Backend, my_node_id: &str, sys_ticket: &str, remote_node: &LinkedNode, long_poll_wait: u64) -> SyncReport {
+    match create_transport(&remote_node.addr) {
+        Ok(transport) => exchange_with_node(backend, my_node_id, sys_ticket, remote_node, transport.as_ref(), long_poll_wait),
+        Err(e) => {
+            error!("fail create transport to {}, err={:?}", remote_node.addr, e);
+            SyncReport {
+                error: Some(ExImCode::TransmitFailed),
+                ..SyncReport::default()
+            }
+        },
+    }
+}
+
+// сеанс обмена с нодой через указанный транспорт
+pub fn exchange_with_node(backend: &mut Backend, my_node_id: &str, sys_ticket: &str, remote_node: &LinkedNode, transport: &dyn Transport, long_poll_wait: u64) -> SyncReport {
+    let mut report = SyncReport::default();
+
+    // по адресу ноды может отвечать другая нода (например, после переустановки)
+    match transport.hello() {
+        Ok(node_id) => {
+            if !node_id.is_empty() && node_id != remote_node.node_id {
+                error!("node {} responds with node_id={}, expected {}, exchange skipped", transport.addr(), node_id, remote_node.node_id);
+                report.error = Some(ExImCode::InvalidTarget);
+                return report;
+            }
+        },
+        Err(e) => {
+            error!("hello {}: {:?}", transport.addr(), e);
+            report.error = Some(ExImCode::TransmitFailed);
+            return report;
+        },
+    }
+
+    // в режиме push отправляются изменения, которые иначе нода запросила бы сама (export_delta)
+    let consumer_prefix = if remote_node.mode == ExchangeMode::Push {
+        "r"
+    } else {
+        "i"
+    };
+    let consumer_name = format!("{}_{}", consumer_prefix, remote_node.node_id.replace(':', "_"));
+    if let Ok(mut queue_consumer) = open_node_consumer(&consumer_name, remote_node.snapshot_required).map_err(|e| error!("{}", e)) {
+        info!("attempt send changes to node {}", consumer_name);
+        let (count_sent, res) = send_changes_to_node(&mut queue_consumer, transport, &remote_node.node_id, remote_node.relay, &remote_node.retry);
+        report.count_sent = count_sent;
+        report.queue_lag = Some(get_queue_lag(&mut queue_consumer));
+        if res != ExImCode::Ok {
+            report.error = Some(res);
+        } else {
+            report.pushed = true;
+        }
+
+        // в режиме peer нода сама отправляет свои изменения, запрос не выполняется
+        if remote_node.mode == ExchangeMode::Inquire || remote_node.mode == ExchangeMode::Push {
+            // request changes from slave node
+            info!("attempt request changes form node {}", consumer_name);
+
+            let (count_recv, res) = recv_changes_from_node(backend, my_node_id, sys_ticket, transport, &consumer_name, long_poll_wait, &remote_node.retry);
+            report.count_recv = count_recv;
+            if res != ExImCode::Ok {
+                report.error = Some(res);
+            } else {
+                report.pulled = true;
+            }
+        }
+    }
+    report
+}
+
+fn recv_changes_from_node(
+    backend: &mut Backend,
+    my_node_id: &str,
+    sys_ticket: &str,
+    transport: &dyn Transport,
+    consumer_name: &str,
+    long_poll_wait: u64,
+    policy: &RetryPolicy,
+) -> (i32, ExImCode) {
+    let my_groups = get_node_group_membership(backend, my_node_id);
+    let mut state = PullState::new(consumer_name, policy.clone());
+    pull_changes(my_node_id, &my_groups, sys_ticket, transport, long_poll_wait, &mut state, &mut backend.mstorage_api)
+}
+
+// недоставленные принятые сообщения хранятся отдельно от отправляемых: in_<consumer>
+pub fn get_received_dead_letters_name(consumer_name: &str) -> String {
+    format!("in_{}", consumer_name)
+}
+
+// прием изменений от ноды: политика повторов, позиции записанных, но еще не подтвержденных сообщений
+// и очередь недоставленных принятых сообщений
+pub struct PullState {
+    pub policy: RetryPolicy,
+    pub applied: AppliedPositions,
+    pub dlq: DeadLetterQueue,
+}
+
+impl PullState {
+    pub fn new(consumer_name: &str, policy: RetryPolicy) -> Self {
+        PullState {
+            policy,
+            applied: AppliedPositions::new(consumer_name),
+            dlq: DeadLetterQueue::new(&get_received_dead_letters_name(consumer_name)),
+        }
+    }
+}
+
+// запрашивает у ноды порции изменений и записывает их в storage. Позиции записанных сообщений сохраняются
+// до подтверждения, поэтому после сбоя подтверждения нода не выдает их повторно. Сообщение, не записанное из-за
+// ошибки из policy.retryable, не подтверждается: прием останавливается, и нода выдаст его повторно. Сообщение,
+// которое не будет принято и при повторе, подтверждается только после переноса в очередь недоставленных
+pub fn pull_changes(my_node_id: &str, my_groups: &[String], sys_ticket: &str, transport: &dyn Transport, long_poll_wait: u64, state: &mut PullState, storage: &mut dyn ImportStorage) -> (i32, ExImCode) {
+    let mut count_recv = 0;
+    let PullState {
+        policy,
+        applied,
+        dlq,
+    } = state;
+
+    // сообщения, записанные до сбоя подтверждения, подтверждаются до запроса новой порции
+    if !applied.is_empty() {
+        if let Err(e) = ack_applied(transport, my_node_id, applied) {
+            error!("fail ack messages to {}, err={:?}", transport.addr(), e);
+            return (count_recv, ExImCode::ReceiveFailed);
+        }
+    }
+
+    loop {
+        // в ожидании удерживается только первый запрос, остальные забирают накопленное
+        let wait = if count_recv == 0 {
+            long_poll_wait
+        } else {
+            0
+        };
+
+        let batch = match transport.pull_batch(my_node_id, PULL_BATCH_SIZE, wait) {
+            Ok(batch) => batch,
+            Err(e) => {
+                error!("fail recv message from {}, err={:?}", transport.addr(), e);
+                return (count_recv, ExImCode::ReceiveFailed);
+            },
+        };
+
+        if batch.is_empty() {
+            break;
+        }
+
+        for recv_msg in batch.iter() {
+            let res = match decode_message(recv_msg) {
+                Ok(recv_pack) if recv_pack.is_empty() => Some((String::default(), IOResult::new("", ExImCode::Ok))),
+                Ok(mut recv_pack) => {
+                    let sender = get_sender(&mut recv_pack);
+                    let res = processing_imported_message(my_node_id, my_groups, &mut recv_pack, sys_ticket, storage);
+                    if res.res_code == ExImCode::Ok {
+                        count_recv += 1;
+                        info!("get {} form node {}", recv_pack.get_id(), transport.addr());
+                    }
+                    Some((sender, res))
+                },
+                Err(e) => {
+                    error!("fail decode message from {}, err={:?}", transport.addr(), e);
+                    None
+                },
+            };
+
+            if let Some(code) = reject_received(dlq, policy, recv_msg, res) {
+                // записанные до сбоя сообщения подтверждаются, остальные нода выдаст повторно
+                if let Err(e) = ack_applied(transport, my_node_id, applied) {
+                    error!("fail ack messages to {}, err={:?}", transport.addr(), e);
+                }
+                return (count_recv, code);
+            }
+
+            // сообщения без позиции (export_delta) нода фиксирует сама при выдаче
+            if let Some(pos) = recv_msg["pos"].as_u64() {
+                if let Err(e) = applied.push(pos) {
+                    error!("fail store applied positions {}, err={:?}", applied.path, e);
+                    return (count_recv, ExImCode::FailUpdate);
+                }
+            }
+        }
+
+        // порция подтверждается после обработки, при сбое нода выдаст неподтвержденные сообщения повторно
+        if let Err(e) = ack_applied(transport, my_node_id, applied) {
+            error!("fail ack messages to {}, err={:?}", transport.addr(), e);
+            return (count_recv, ExImCode::ReceiveFailed);
+        }
+    }
+    (count_recv, ExImCode::Ok)
+}
+
+// None - сообщение записано либо перенесено в очередь недоставленных, его можно подтвердить;
+// иначе код ошибки, с которой прием останавливается без подтверждения сообщения
+fn reject_received(dlq: &mut DeadLetterQueue, policy: &RetryPolicy, recv_msg: &JSONValue, res: Option<(String, IOResult)>) -> Option<ExImCode> {
+    let (sender, msg_id, code, detail) = match res {
+        Some((_, r)) if r.res_code == ExImCode::Ok => return None,
+        Some((sender, r)) => {
+            error!("fail accept changes, uri={}, err={:?}, detail={}, recv_msg={:?}", r.id, r.res_code, r.detail.as_deref().unwrap_or_default(), recv_msg);
+            if policy.is_retryable(&r.res_code) {
+                return Some(r.res_code);
+            }
+            (sender, r.id, r.res_code, r.detail)
+        },
+        None => (String::default(), String::default(), ExImCode::InvalidMessage, Some("fail decode message".to_owned())),
+    };
+
+    if dlq.threshold == 0 {
+        return Some(code);
+    }
+    let mut letter = DeadLetter::new(&sender, &msg_id, code.clone(), detail, 1, recv_msg.clone());
+    letter.received = true;
+    if let Err(e) = dlq.put(&dead_letter_key(&msg_id, recv_msg), &letter) {
+        error!("fail store dead letter, id={}, err={:?}", msg_id, e);
+        return Some(code);
+    }
+    None
+}
+
+fn ack_applied(transport: &dyn Transport, my_node_id: &str, applied: &mut AppliedPositions) -> Result<(), Box<dyn Error>> {
+    transport.ack(my_node_id, applied.positions())?;
+    applied.clear()?;
+    Ok(())
+}
+
+// позиции записанных, но еще не подтвержденных ноде сообщений, файл ./data/out/applied/<consumer>
+pub struct AppliedPositions {
+    dir: String,
+    path: String,
+    positions: Vec<u64>,
+}
+
+impl AppliedPositions {
+    pub fn new(consumer_name: &str) -> Self {
+        AppliedPositions::with_path(APPLIED_PATH, consumer_name)
+    }
+
+    pub fn with_path(dir: &str, consumer_name: &str) -> Self {
+        let path = format!("{}/{}", dir, consumer_name);
+        let mut positions = vec![];
+        if let Ok(f) = File::open(&path) {
+            positions = BufReader::new(f).lines().map_while(Result::ok).filter_map(|l| l.trim().parse::<u64>().ok()).collect();
+        }
+        AppliedPositions {
+            dir: dir.to_owned(),
+            path,
+            positions,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn positions(&self) -> &[u64] {
+        &self.positions
+    }
+
+    fn push(&mut self, pos: u64) -> io::Result<()> {
+        create_dir_all(&self.dir)?;
+        let mut f = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        f.write_all(format!("{}\n", pos).as_bytes())?;
+        self.positions.push(pos);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        if let Err(e) = remove_file(&self.path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(e);
+            }
+        }
+        self.positions.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_message;
+    use crate::message::ExImMessage;
+    use crate::storage::MemoryStorage;
+    use crate::transport::MemoryTransport;
+    use v_common::onto::datatype::Lang;
+    use v_common::onto::individual::Individual;
+    use v_common::onto::individual2msgpack::to_msgpack;
+    use v_common::v_api::api_client::IndvOp;
+
+    const MY_NODE: &str = "sys:00000000-0000-0000-0000-000000000001";
+    const REMOTE_NODE: &str = "sys:00000000-0000-0000-0000-000000000002";
+
+    fn add_to_message(idx: usize) -> serde_json::Value {
+        let mut state = Individual::default();
+        state.set_id("d:counter");
+        state.add_string("rdfs:label", &format!("label {}", idx), Lang::none());
+        let mut new_state = vec![];
+        to_msgpack(&state, &mut new_state).unwrap();
+
+        let msg = ExImMessage {
+            id: format!("msg:{}", idx),
+            uri: "d:counter".to_owned(),
+            cmd: IndvOp::AddTo,
+            new_state: Some(new_state),
+            date: 1,
+            source_veda: REMOTE_NODE.to_owned(),
+            target_veda: vec![MY_NODE.to_owned()],
+            enable_scripts: false,
+            visited: vec![REMOTE_NODE.to_owned()],
+        };
+        encode_message(&mut msg.to_individual()).unwrap()
+    }
+
+    // файлы каждого запуска тестов в отдельном временном каталоге
+    fn test_dir(name: &str) -> String {
+        std::env::temp_dir().join(format!("v_exim_{}_{}", name, std::process::id())).to_string_lossy().to_string()
+    }
+
+    fn applied_positions(consumer_name: &str) -> AppliedPositions {
+        AppliedPositions::with_path(&test_dir("applied"), consumer_name)
+    }
+
+    fn pull_state(consumer_name: &str) -> PullState {
+        PullState {
+            policy: RetryPolicy::default(),
+            applied: applied_positions(consumer_name),
+            dlq: DeadLetterQueue::with_path(&test_dir("dead"), consumer_name),
+        }
+    }
+
+    fn invalid_message() -> serde_json::Value {
+        // AddTo без нового состояния не может быть записан ни при какой попытке
+        let msg = ExImMessage {
+            id: "msg:invalid".to_owned(),
+            uri: "d:counter".to_owned(),
+            cmd: IndvOp::AddTo,
+            new_state: None,
+            date: 1,
+            source_veda: REMOTE_NODE.to_owned(),
+            target_veda: vec![MY_NODE.to_owned()],
+            enable_scripts: false,
+            visited: vec![REMOTE_NODE.to_owned()],
+        };
+        encode_message(&mut msg.to_individual()).unwrap()
+    }
+
+    fn count_add_to(storage: &MemoryStorage) -> usize {
+        storage.updates.iter().filter(|(cmd, _)| *cmd == IndvOp::AddTo).count()
+    }
+
+    #[test]
+    fn pull_applies_and_acks_batch() {
+        let consumer_name = "test_pull_ack";
+        let transport = MemoryTransport::new(REMOTE_NODE);
+        for idx in 0..3 {
+            transport.push_outgoing(add_to_message(idx));
+        }
+
+        let mut storage = MemoryStorage::new();
+        let (count_recv, res) = pull_changes(MY_NODE, &[], "", &transport, 0, &mut pull_state(consumer_name), &mut storage);
+
+        assert_eq!(res, ExImCode::Ok);
+        assert_eq!(count_recv, 3);
+        assert_eq!(count_add_to(&storage), 3);
+        assert_eq!(transport.count_outgoing(), 0);
+        assert!(applied_positions(consumer_name).is_empty());
+    }
+
+    #[test]
+    fn unacked_batch_is_redelivered_but_not_reapplied() {
+        let consumer_name = "test_pull_redelivery";
+        let transport = MemoryTransport::new(REMOTE_NODE);
+        for idx in 0..3 {
+            transport.push_outgoing(add_to_message(idx));
+        }
+
+        // сообщения записаны, подтверждение не дошло до ноды
+        transport.set_fail_ack(true);
+        let mut storage = MemoryStorage::new();
+        let (count_recv, res) = pull_changes(MY_NODE, &[], "", &transport, 0, &mut pull_state(consumer_name), &mut storage);
+        assert_eq!(res, ExImCode::ReceiveFailed);
+        assert_eq!(count_recv, 3);
+        assert_eq!(transport.count_outgoing(), 3);
+        assert_eq!(applied_positions(consumer_name).positions().len(), 3);
+
+        // пока подтверждение не выполнено, новая порция не запрашивается
+        let (_, res) = pull_changes(MY_NODE, &[], "", &transport, 0, &mut pull_state(consumer_name), &mut storage);
+        assert_eq!(res, ExImCode::ReceiveFailed);
+        assert_eq!(count_add_to(&storage), 3);
+
+        // в следующем сеансе записанные сообщения подтверждаются и повторно не применяются
+        transport.set_fail_ack(false);
+        transport.push_outgoing(add_to_message(3));
+        let (count_recv, res) = pull_changes(MY_NODE, &[], "", &transport, 0, &mut pull_state(consumer_name), &mut storage);
+        assert_eq!(res, ExImCode::Ok);
+        assert_eq!(count_recv, 1);
+        assert_eq!(count_add_to(&storage), 4);
+        assert_eq!(transport.count_outgoing(), 0);
+        assert!(applied_positions(consumer_name).is_empty());
+    }
+
+    #[test]
+    fn failed_update_is_not_acked() {
+        let consumer_name = "test_pull_fail_update";
+        let transport = MemoryTransport::new(REMOTE_NODE);
+        for idx in 0..3 {
+            transport.push_outgoing(add_to_message(idx));
+        }
+
+        // сбой хранилища повторяемый: сообщения остаются у ноды
+        let mut storage = MemoryStorage::new();
+        storage.fail_update = true;
+        let (count_recv, res) = pull_changes(MY_NODE, &[], "", &transport, 0, &mut pull_state(consumer_name), &mut storage);
+        assert_eq!(res, ExImCode::FailUpdate);
+        assert_eq!(count_recv, 0);
+        assert_eq!(transport.count_outgoing(), 3);
+        assert!(applied_positions(consumer_name).is_empty());
+
+        storage.fail_update = false;
+        let (count_recv, res) = pull_changes(MY_NODE, &[], "", &transport, 0, &mut pull_state(consumer_name), &mut storage);
+        assert_eq!(res, ExImCode::Ok);
+        assert_eq!(count_recv, 3);
+        assert_eq!(transport.count_outgoing(), 0);
+    }
+
+    #[test]
+    fn invalid_message_is_dead_lettered_and_acked() {
+        let consumer_name = "test_pull_invalid";
+        let transport = MemoryTransport::new(REMOTE_NODE);
+        transport.push_outgoing(add_to_message(0));
+        transport.push_outgoing(invalid_message());
+        transport.push_outgoing(add_to_message(1));
+
+        let mut storage = MemoryStorage::new();
+        let mut state = pull_state(consumer_name);
+        let (count_recv, res) = pull_changes(MY_NODE, &[], "", &transport, 0, &mut state, &mut storage);
+        assert_eq!(res, ExImCode::Ok);
+        assert_eq!(count_recv, 2);
+        assert_eq!(transport.count_outgoing(), 0);
+
+        let letters = state.dlq.list();
+        assert_eq!(letters.len(), 1);
+        assert!(letters[0].received);
+        assert_eq!(letters[0].error, ExImCode::InvalidMessage);
+    }
+
+    #[test]
+    fn ack_removes_only_given_positions() {
+        let transport = MemoryTransport::new(REMOTE_NODE);
+        for idx in 0..3 {
+            transport.push_outgoing(add_to_message(idx));
+        }
+
+        let batch = transport.pull_batch(MY_NODE, 10, 0).unwrap();
+        let positions: Vec<u64> = batch.iter().filter_map(|m| m["pos"].as_u64()).collect();
+        assert_eq!(positions.len(), 3);
+
+        transport.ack(MY_NODE, &positions[1..2]).unwrap();
+        let rest: Vec<u64> = transport.pull_batch(MY_NODE, 10, 0).unwrap().iter().filter_map(|m| m["pos"].as_u64()).collect();
+        assert_eq!(rest, vec![positions[0], positions[2]]);
+
+        // повторное подтверждение той же позиции ничего не удаляет
+        transport.ack(MY_NODE, &positions[1..2]).unwrap();
+        assert_eq!(transport.count_outgoing(), 2);
+    }
+}
@@ -0,0 +1,61 @@
+/*
+ * Описание связанной ноды (v-s:LinkedNode)
+ */
+use crate::retry::RetryPolicy;
+use crate::snapshot::is_snapshot_required;
+use v_common::onto::individual::Individual;
+
+// режим обмена с нодой (cfg:exim_mode)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExchangeMode {
+    // эта нода (master) отправляет свои изменения и запрашивает изменения у ноды (slave)
+    Inquire,
+    // симметричный обмен: каждая из нод только отправляет свои изменения другой
+    Peer,
+    // нода (slave) сама отправляет изменения для master (потребитель r_<node>) и запрашивает у него изменения,
+    // используется, если master не может установить соединение со slave
+    Push,
+    // нода сама выполняет обмен с этой нодой (режим push на ее стороне), сеансы обмена не инициируются
+    Passive,
+}
+
+impl From<&str> for ExchangeMode {
+    fn from(value: &str) -> Self {
+        match value {
+            "peer" => ExchangeMode::Peer,
+            "push" => ExchangeMode::Push,
+            "passive" => ExchangeMode::Passive,
+            _ => ExchangeMode::Inquire,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkedNode {
+    // id индивида v-s:LinkedNode
+    pub id: String,
+    pub node_id: String,
+    pub addr: String,
+    // нода является транзитной (hub): ей передаются сообщения для нод, с которыми нет прямой связи
+    pub relay: bool,
+    pub mode: ExchangeMode,
+    pub retry: RetryPolicy,
+    // для ноды запрошена или выполняется полная выгрузка (cfg:snapshot_status)
+    pub snapshot_required: bool,
+}
+
+impl LinkedNode {
+    pub fn new(link_node: &mut Individual) -> Option<Self> {
+        let addr = link_node.get_first_literal("rdf:value")?;
+
+        Some(LinkedNode {
+            id: link_node.get_id().to_owned(),
+            node_id: link_node.get_first_literal("cfg:node_id").unwrap_or_default(),
+            addr,
+            relay: link_node.get_first_bool("cfg:relay").unwrap_or(false),
+            mode: ExchangeMode::from(link_node.get_first_literal("cfg:exim_mode").unwrap_or_default().as_str()),
+            retry: RetryPolicy::for_node(link_node, &RetryPolicy::from_properties()),
+            snapshot_required: is_snapshot_required(link_node),
+        })
+    }
+}
@@ -0,0 +1,118 @@
+/*
+ * Сообщение обмена: элемент очереди ./data/out/extract и сообщение,
+ * передаваемое между нодами. Проверка сообщения выполняется в validate
+ */
+use crate::error::ExImError;
+use v_common::onto::datatype::Lang;
+use v_common::onto::individual::{Individual, RawObj};
+use v_common::onto::parser::parse_raw;
+use v_common::v_api::api_client::IndvOp;
+
+// маршрут принятого обменом изменения, сохраняется в индивиде при записи (см. processing_imported_message)
+pub const VISITED_PREDICATE: &str = "sys:visited";
+
+#[derive(Clone)]
+pub struct ExImMessage {
+    pub id: String,
+    // индивид, к которому относится изменение
+    pub uri: String,
+    pub cmd: IndvOp,
+    // состояние индивида (msgpack), для Remove может отсутствовать
+    pub new_state: Option<Vec<u8>>,
+    pub date: i64,
+    pub source_veda: String,
+    // адресаты: node_id, "*" или группы нод
+    pub target_veda: Vec<String>,
+    pub enable_scripts: bool,
+    // ноды, через которые изменение уже прошло
+    pub visited: Vec<String>,
+}
+
+impl ExImMessage {
+    pub fn from_individual(msg: &mut Individual) -> Result<Self, ExImError> {
+        let cmd = msg.get_first_integer("cmd").ok_or_else(|| ExImError::InvalidCmd("cmd is missing".to_owned()))?;
+
+        let res = ExImMessage {
+            id: msg.get_id().to_owned(),
+            uri: msg.get_first_literal("uri").unwrap_or_default(),
+            cmd: IndvOp::from_i64(cmd),
+            new_state: msg.get_first_binobj("new_state"),
+            date: msg.get_first_integer("date").ok_or(ExImError::InvalidField("date"))?,
+            source_veda: msg.get_first_literal("source_veda").unwrap_or_default(),
+            target_veda: msg.get_literals("target_veda").unwrap_or_default(),
+            enable_scripts: msg.get_first_bool("enable_scripts").unwrap_or(false),
+            visited: msg.get_literals("visited").unwrap_or_default(),
+        };
+        res.validate()?;
+        Ok(res)
+    }
+
+    pub fn validate(&self) -> Result<(), ExImError> {
+        if !matches!(self.cmd, IndvOp::Put | IndvOp::Remove | IndvOp::AddTo | IndvOp::RemoveFrom | IndvOp::SetIn) {
+            return Err(ExImError::InvalidCmd(format!("unsupported cmd {}", self.cmd.as_string())));
+        }
+
+        if self.uri.is_empty() {
+            return Err(ExImError::InvalidField("uri"));
+        }
+
+        // идентификатор ноды: sys:<uuid>
+        if self.source_veda.len() < 32 {
+            return Err(ExImError::InvalidTarget(format!("invalid source_veda [{}]", self.source_veda)));
+        }
+        if self.target_veda.is_empty() {
+            return Err(ExImError::InvalidTarget("target_veda is empty".to_owned()));
+        }
+
+        match &self.new_state {
+            Some(_) => {
+                if self.get_new_state().is_none() {
+                    return Err(ExImError::InvalidField("new_state"));
+                }
+            },
+            None => {
+                if self.cmd != IndvOp::Remove {
+                    return Err(ExImError::InvalidField("new_state"));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    pub fn get_new_state(&self) -> Option<Individual> {
+        let mut indv = Individual::new_raw(RawObj::new(self.new_state.clone()?));
+        if parse_raw(&mut indv).is_err() {
+            return None;
+        }
+        indv.parse_all();
+        Some(indv)
+    }
+
+    pub fn to_individual(&self) -> Individual {
+        let mut msg = Individual::default();
+        msg.set_id(&self.id);
+        msg.add_uri("uri", &self.uri);
+        if let Some(new_state) = &self.new_state {
+            msg.add_binary("new_state", new_state.clone());
+        }
+        msg.add_integer("cmd", self.cmd.to_i64());
+        msg.add_integer("date", self.date);
+        msg.add_string("source_veda", &self.source_veda, Lang::none());
+        for target in self.target_veda.iter() {
+            msg.add_string("target_veda", target, Lang::none());
+        }
+        msg.add_bool("enable_scripts", self.enable_scripts);
+        for node_id in self.visited.iter() {
+            msg.add_string("visited", node_id, Lang::none());
+        }
+        msg
+    }
+}
+
+// нода, передавшая сообщение: последняя транзитная нода, для изменений без транзита - источник (source_veda)
+pub fn get_sender(msg: &mut Individual) -> String {
+    match msg.get_literals("visited").and_then(|v| v.last().cloned()) {
+        Some(node_id) => node_id,
+        None => msg.get_first_literal("source_veda").unwrap_or_default(),
+    }
+}
@@ -0,0 +1,258 @@
+/*
+ * Обслуживание очереди ./data/out/extract и ее потребителей (i_<node>, r_<node>):
+ * список частей, позиции и отставание потребителей, перемещение и удаление
+ * потребителя, удаление прочитанных частей. Перемещать потребителя можно только
+ * при остановленных модулях обмена
+ */
+use crate::coalesce::PendingWindow;
+use crate::linked_node::{ExchangeMode, LinkedNode};
+use std::fs::{copy, create_dir_all, read_dir, remove_dir_all, remove_file, rename};
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+use v_common::module::module_impl::Module;
+use v_common::onto::individual::{Individual, RawObj};
+use v_common::onto::parser::parse_raw;
+use v_queue::consumer::Consumer;
+use v_queue::queue::{Mode, Queue};
+use v_queue::record::ErrorQueue;
+
+pub const QUEUE_BASE_PATH: &str = "./data/out";
+pub const QUEUE_NAME: &str = "extract";
+
+// позиция потребителя хранится в файле <base_path>/<queue_name>_info_pop_<consumer>
+fn get_consumer_info_prefix() -> String {
+    format!("{}_info_pop_", QUEUE_NAME)
+}
+
+// сколько дней хранятся части очереди, прочитанные всеми потребителями
+pub fn get_queue_retention_days() -> u64 {
+    Module::get_property("exim_queue_retention_days").unwrap_or_default().parse::<u64>().unwrap_or(7)
+}
+
+// каталог части очереди
+fn get_part_path(id: u32) -> String {
+    format!("{}/{}-{}", QUEUE_BASE_PATH, QUEUE_NAME, id)
+}
+
+pub struct PartInfo {
+    pub id: u32,
+    pub count_pushed: u32,
+}
+
+pub struct ConsumerInfo {
+    pub name: String,
+    pub part_id: u32,
+    pub count_popped: u32,
+    // число непрочитанных сообщений во всех частях очереди
+    pub lag: i64,
+}
+
+pub fn open_queue() -> Result<Queue, String> {
+    Queue::new(QUEUE_BASE_PATH, QUEUE_NAME, Mode::Read).map_err(|e| format!("fail open queue {}: {}", QUEUE_NAME, e.as_str()))
+}
+
+// существующие части очереди, части до первой имеющейся могут быть удалены (purge)
+pub fn list_parts() -> Result<Vec<PartInfo>, String> {
+    let mut queue = open_queue()?;
+    queue.get_info_queue();
+    let last_id = queue.id;
+
+    let mut res = vec![];
+    for id in 0..=last_id {
+        if queue.get_info_of_part(id, false).is_ok() {
+            res.push(PartInfo {
+                id,
+                count_pushed: queue.count_pushed,
+            });
+        }
+    }
+    Ok(res)
+}
+
+// потребитель, из которого нода получает изменения этой ноды
+pub fn get_node_consumer_name(node: &LinkedNode) -> String {
+    let prefix = match node.mode {
+        ExchangeMode::Inquire | ExchangeMode::Peer => "i",
+        ExchangeMode::Push | ExchangeMode::Passive => "r",
+    };
+    format!("{}_{}", prefix, node.node_id.replace(':', "_"))
+}
+
+pub fn is_consumer_exists(name: &str) -> bool {
+    Path::new(&format!("{}/{}{}", QUEUE_BASE_PATH, get_consumer_info_prefix(), name)).exists()
+}
+
+pub fn list_consumer_names() -> Vec<String> {
+    let prefix = get_consumer_info_prefix();
+    let mut res = vec![];
+    if let Ok(entries) = read_dir(QUEUE_BASE_PATH) {
+        for entry in entries.flatten() {
+            if let Some(name) = entry.file_name().to_string_lossy().strip_prefix(&prefix) {
+                res.push(name.to_owned());
+            }
+        }
+    }
+    res.sort();
+    res
+}
+
+pub fn open_consumer(name: &str) -> Result<Consumer, String> {
+    Consumer::new(QUEUE_BASE_PATH, name, QUEUE_NAME).map_err(|e| format!("fail open queue consumer {}: {}", name, e.as_str()))
+}
+
+// потребитель обмена с нодой (i_<node>, r_<node>, b_<node>). Новый потребитель устанавливается на первую имеющуюся
+// часть очереди, так как части до нее могли быть удалены (purge) до того, как нода была связана. Если для ноды
+// запрошена полная выгрузка (snapshot_required), новый потребитель устанавливается в конец очереди: накопленные
+// изменения нода получит в составе выгрузки
+pub fn open_node_consumer(name: &str, snapshot_required: bool) -> Result<Consumer, String> {
+    let is_new = !is_consumer_exists(name);
+    let mut consumer = open_consumer(name)?;
+    if is_new {
+        let parts = list_parts()?;
+        if snapshot_required {
+            if let Some(last_part) = parts.last() {
+                rewind_consumer(&mut consumer, last_part.id, last_part.count_pushed)?;
+                info!("new consumer {} starts from end of queue, part {}, position {}", name, last_part.id, last_part.count_pushed);
+            }
+        } else if let Some(first_part) = parts.first() {
+            if first_part.id > consumer.id {
+                rewind_consumer(&mut consumer, first_part.id, 0)?;
+                info!("new consumer {} starts from first existing part {}", name, first_part.id);
+            }
+        }
+    }
+    Ok(consumer)
+}
+
+pub fn get_consumer_info(name: &str, parts: &[PartInfo]) -> Result<ConsumerInfo, String> {
+    let consumer = open_consumer(name)?;
+
+    let mut lag = 0;
+    for part in parts.iter().filter(|p| p.id >= consumer.id) {
+        lag += part.count_pushed as i64;
+        if part.id == consumer.id {
+            lag -= consumer.count_popped as i64;
+        }
+    }
+
+    Ok(ConsumerInfo {
+        name: name.to_owned(),
+        part_id: consumer.id,
+        count_popped: consumer.count_popped,
+        lag,
+    })
+}
+
+// следующий элемент очереди, позиция фиксируется вызовом consumer.commit()
+pub fn peek_next(consumer: &mut Consumer) -> Option<Vec<u8>> {
+    if !consumer.pop_header() {
+        // часть прочитана полностью, проверяем не появились ли новые сообщения или следующая часть
+        if let Err(e) = consumer.queue.get_info_of_part(consumer.id, true) {
+            error!("get_info_of_part {}: {}", consumer.id, e.as_str());
+            return None;
+        }
+        consumer.queue.get_info_queue();
+        if !consumer.pop_header() {
+            return None;
+        }
+    }
+
+    let mut raw = RawObj::new(vec![0; (consumer.header.msg_length) as usize]);
+    if let Err(e) = consumer.pop_body(&mut raw.data) {
+        if e != ErrorQueue::FailReadTailMessage {
+            error!("get msg from queue: {}", e.as_str());
+        }
+        return None;
+    }
+    Some(raw.data)
+}
+
+// устанавливает потребителя на position сообщение части part_id. Прочитанные, но не переданные
+// сообщения окна ожидания отбрасываются, при перемещении назад они будут прочитаны повторно
+pub fn rewind_consumer(consumer: &mut Consumer, part_id: u32, position: u32) -> Result<(), String> {
+    PendingWindow::new(&consumer.name).remove().map_err(|e| format!("fail remove pending window of {}: {:?}", consumer.name, e))?;
+    consumer.queue.open_part(part_id).map_err(|e| format!("fail open part {}: {}", part_id, e.as_str()))?;
+    consumer.id = part_id;
+    consumer.pos_record = 0;
+    consumer.count_popped = 0;
+    if !consumer.put_info() {
+        return Err(format!("fail store position of consumer {}", consumer.name));
+    }
+
+    for _ in 0..position {
+        if consumer.id != part_id || peek_next(consumer).is_none() {
+            return Err(format!("part {} contains less than {} messages", part_id, position));
+        }
+        consumer.commit();
+    }
+    Ok(())
+}
+
+// дата изменения элемента очереди
+pub fn get_queue_element_date(raw: &[u8]) -> Option<i64> {
+    let mut queue_element = Individual::new_raw(RawObj::new(raw.to_vec()));
+    parse_raw(&mut queue_element).ok()?;
+    queue_element.get_first_integer("date")
+}
+
+// устанавливает потребителя на первое сообщение с датой изменения не ранее date, возвращает число пропущенных
+pub fn seek_consumer_to_date(consumer: &mut Consumer, date: i64) -> Result<u64, String> {
+    let first_part = list_parts()?.first().map(|p| p.id).ok_or("queue is empty")?;
+    rewind_consumer(consumer, first_part, 0)?;
+
+    let mut skipped = 0;
+    while let Some(raw) = peek_next(consumer) {
+        if get_queue_element_date(&raw).map(|d| d >= date).unwrap_or(false) {
+            break;
+        }
+        consumer.commit();
+        skipped += 1;
+    }
+    Ok(skipped)
+}
+
+// устанавливает потребителя в конец очереди, все накопленные сообщения (и окно ожидания) пропускаются
+pub fn reset_consumer(consumer: &mut Consumer) -> Result<u64, String> {
+    PendingWindow::new(&consumer.name).remove().map_err(|e| format!("fail remove pending window of {}: {:?}", consumer.name, e))?;
+    let mut skipped = 0;
+    while peek_next(consumer).is_some() {
+        consumer.commit();
+        skipped += 1;
+    }
+    Ok(skipped)
+}
+
+// удаляет потребителя и его окно ожидания, недоставленные сообщения (dead letters) сохраняются
+pub fn delete_consumer(name: &str) -> io::Result<()> {
+    remove_file(format!("{}/{}{}", QUEUE_BASE_PATH, get_consumer_info_prefix(), name))?;
+    PendingWindow::new(name).remove()
+}
+
+// время последней записи в часть очереди
+pub fn get_part_modified(id: u32) -> io::Result<SystemTime> {
+    let mut res = SystemTime::UNIX_EPOCH;
+    for entry in read_dir(get_part_path(id))? {
+        res = res.max(entry?.metadata()?.modified()?);
+    }
+    Ok(res)
+}
+
+// удаляет часть очереди либо переносит ее в каталог archive
+pub fn remove_part(id: u32, archive: Option<&str>) -> io::Result<()> {
+    let path = get_part_path(id);
+    if let Some(archive) = archive {
+        let dest = format!("{}/{}-{}", archive, QUEUE_NAME, id);
+        create_dir_all(archive)?;
+        if rename(&path, &dest).is_ok() {
+            return Ok(());
+        }
+        // каталог архива на другой файловой системе
+        create_dir_all(&dest)?;
+        for entry in read_dir(&path)? {
+            let entry = entry?;
+            copy(entry.path(), Path::new(&dest).join(entry.file_name()))?;
+        }
+    }
+    remove_dir_all(path)
+}
@@ -0,0 +1,59 @@
+/*
+ * Пересылка через транзитную ноду. Принятое сообщение, адресованное другим нодам,
+ * без изменений (кроме адресатов и списка посещенных нод) записывается в каталог
+ * ./data/out/relay, veda-extractor переносит такие сообщения в очередь
+ * ./data/out/extract в порядке поступления
+ */
+use crate::message::ExImMessage;
+use std::fs::{create_dir_all, read, read_dir, remove_file, rename, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use v_common::onto::individual2msgpack::to_msgpack;
+
+const RELAY_PATH: &str = "./data/out/relay";
+const RELAY_EXT: &str = "msg";
+
+// порядок сообщений одного процесса, принятых в одну и ту же наносекунду
+static RELAY_SEQ: AtomicU64 = AtomicU64::new(0);
+
+// записывает сообщение в формате элемента очереди ./data/out/extract
+pub fn put_relay_message(msg: &ExImMessage) -> io::Result<()> {
+    let mut raw = vec![];
+    to_msgpack(&msg.to_individual(), &mut raw).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+
+    create_dir_all(RELAY_PATH)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+    let name = format!("{:024}_{:010}_{}", now, RELAY_SEQ.fetch_add(1, Ordering::Relaxed), process::id());
+
+    let tmp_path = format!("{}/{}.tmp", RELAY_PATH, name);
+    let mut f = File::create(&tmp_path)?;
+    f.write_all(&raw)?;
+    f.sync_all()?;
+    rename(&tmp_path, format!("{}/{}.{}", RELAY_PATH, name, RELAY_EXT))
+}
+
+// сообщения для пересылки в порядке поступления
+pub fn list_relay_messages() -> Vec<PathBuf> {
+    let mut res = vec![];
+    if let Ok(entries) = read_dir(RELAY_PATH) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().map(|e| e == RELAY_EXT).unwrap_or(false) {
+                res.push(path);
+            }
+        }
+    }
+    res.sort();
+    res
+}
+
+pub fn read_relay_message(path: &PathBuf) -> io::Result<Vec<u8>> {
+    read(path)
+}
+
+pub fn remove_relay_message(path: &PathBuf) -> io::Result<()> {
+    remove_file(path)
+}
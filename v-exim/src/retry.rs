@@ -0,0 +1,140 @@
+/*
+ * Политика повторных попыток обмена с нодой: число попыток, экспоненциальная
+ * задержка со случайным разбросом и коды ExImCode, при которых попытка
+ * повторяется. Значения по умолчанию задаются параметрами exim_retry_*,
+ * для связанной ноды переопределяются в ее описании (cfg:retry_*).
+ * Пауза между сеансами обмена без изменений задается отдельно (IdlePolicy)
+ */
+use crate::ExImCode;
+use rand::Rng;
+use std::time::Duration;
+use v_common::module::module_impl::Module;
+use v_common::onto::individual::Individual;
+
+const ALL_CODES: [ExImCode; 8] = [
+    ExImCode::InvalidMessage,
+    ExImCode::InvalidCmd,
+    ExImCode::InvalidTarget,
+    ExImCode::FailUpdate,
+    ExImCode::TransmitFailed,
+    ExImCode::SendFailed,
+    ExImCode::ReceiveFailed,
+    ExImCode::Unknown,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    // задержка (мс) перед первым повтором, далее удваивается до max_delay
+    pub base_delay: u64,
+    pub max_delay: u64,
+    // доля случайного разброса задержки, 0..1
+    pub jitter: f64,
+    pub retryable: Vec<ExImCode>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 10,
+            base_delay: 100,
+            max_delay: 30000,
+            jitter: 0.2,
+            retryable: vec![ExImCode::FailUpdate, ExImCode::TransmitFailed, ExImCode::SendFailed, ExImCode::ReceiveFailed],
+        }
+    }
+}
+
+// коды задаются списком через запятую в виде ExImCode::as_string ("fail update, fail send")
+fn parse_codes(values: &[String]) -> Vec<ExImCode> {
+    let mut res = vec![];
+    for value in values.iter().flat_map(|v| v.split(',')) {
+        let value = value.trim();
+        match ALL_CODES.iter().find(|c| c.as_string() == value) {
+            Some(code) => res.push(code.clone()),
+            None => {
+                if !value.is_empty() {
+                    warn!("retry policy: unknown code [{}]", value);
+                }
+            },
+        }
+    }
+    res
+}
+
+impl RetryPolicy {
+    pub fn from_properties() -> Self {
+        let default = RetryPolicy::default();
+        let get = |name: &str| Module::get_property(name).filter(|v| !v.is_empty());
+
+        RetryPolicy {
+            max_attempts: get("exim_retry_max_attempts").and_then(|v| v.parse::<u32>().ok()).unwrap_or(default.max_attempts).max(1),
+            base_delay: get("exim_retry_base_delay").and_then(|v| v.parse().ok()).unwrap_or(default.base_delay),
+            max_delay: get("exim_retry_max_delay").and_then(|v| v.parse().ok()).unwrap_or(default.max_delay),
+            jitter: get("exim_retry_jitter").and_then(|v| v.parse().ok()).unwrap_or(default.jitter),
+            retryable: get("exim_retry_codes").map(|v| parse_codes(&[v])).unwrap_or(default.retryable),
+        }
+    }
+
+    // параметры из описания связанной ноды, не заданные берутся из default
+    pub fn for_node(link_node: &mut Individual, default: &RetryPolicy) -> Self {
+        RetryPolicy {
+            // хотя бы одна попытка, иначе сообщения не передавались бы совсем
+            max_attempts: link_node.get_first_integer("cfg:retry_max_attempts").map(|v| v.clamp(1, u32::MAX as i64) as u32).unwrap_or(default.max_attempts).max(1),
+            base_delay: link_node.get_first_integer("cfg:retry_base_delay").map(|v| v as u64).unwrap_or(default.base_delay),
+            max_delay: link_node.get_first_integer("cfg:retry_max_delay").map(|v| v as u64).unwrap_or(default.max_delay),
+            jitter: link_node.get_first_float("cfg:retry_jitter").unwrap_or(default.jitter),
+            retryable: link_node.get_literals("cfg:retry_codes").map(|v| parse_codes(&v)).unwrap_or_else(|| default.retryable.clone()),
+        }
+    }
+
+    pub fn is_retryable(&self, code: &ExImCode) -> bool {
+        self.retryable.contains(code)
+    }
+
+    // задержка перед повтором с номером attempt (начиная с 0)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay.saturating_mul(1u64 << attempt.min(32)).min(self.max_delay);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 || delay == 0 {
+            return Duration::from_millis(delay);
+        }
+        let k = rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter);
+        Duration::from_millis((delay as f64 * k) as u64)
+    }
+}
+
+// пауза между сеансами обмена, в которых не было изменений: начинается с delay и после каждого
+// такого сеанса увеличивается на delay, не более max_delay (мс)
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdlePolicy {
+    pub delay: u64,
+    pub max_delay: u64,
+}
+
+impl Default for IdlePolicy {
+    fn default() -> Self {
+        IdlePolicy {
+            delay: 1000,
+            max_delay: 30000,
+        }
+    }
+}
+
+impl IdlePolicy {
+    pub fn from_properties() -> Self {
+        let default = IdlePolicy::default();
+        let get = |name: &str| Module::get_property(name).filter(|v| !v.is_empty()).and_then(|v| v.parse::<u64>().ok());
+
+        IdlePolicy {
+            delay: get("exim_idle_delay").unwrap_or(default.delay),
+            max_delay: get("exim_idle_max_delay").unwrap_or(default.max_delay),
+        }
+    }
+
+    // пауза после idle_rounds сеансов подряд без изменений (начиная с 0)
+    pub fn delay(&self, idle_rounds: u32) -> Duration {
+        Duration::from_millis(self.delay.saturating_mul(idle_rounds as u64 + 1).min(self.max_delay))
+    }
+}
@@ -0,0 +1,140 @@
+/*
+ * Состояние первичной полной выгрузки (snapshot) для связанной ноды.
+ * Статус хранится в индивиде v-s:LinkedNode в поле cfg:snapshot_status:
+ * required -> in_progress -> done (или failed), позиция выполняемой выгрузки (номер запроса
+ * и начало очередной порции) - в полях cfg:snapshot_query_index и cfg:snapshot_from. Выполненные выгрузки запоминаются
+ * в ./data/out/snapshots.json, чтобы после перезапуска не повторять выгрузку,
+ * статус done которой не удалось записать
+ */
+use crate::get_linked_nodes;
+use std::collections::HashMap;
+use std::fs::{rename, File};
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use v_common::module::veda_backend::Backend;
+use v_common::onto::datatype::Lang;
+use v_common::onto::individual::Individual;
+use v_common::v_api::api_client::IndvOp;
+use v_common::v_api::obj::ResultCode;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SnapshotStatus {
+    None,
+    Required,
+    InProgress,
+    Done,
+    Failed,
+}
+
+impl SnapshotStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotStatus::None => "",
+            SnapshotStatus::Required => "required",
+            SnapshotStatus::InProgress => "in_progress",
+            SnapshotStatus::Done => "done",
+            SnapshotStatus::Failed => "failed",
+        }
+    }
+}
+
+impl From<&str> for SnapshotStatus {
+    fn from(value: &str) -> Self {
+        match value {
+            "required" => SnapshotStatus::Required,
+            "in_progress" => SnapshotStatus::InProgress,
+            "done" => SnapshotStatus::Done,
+            "failed" => SnapshotStatus::Failed,
+            _ => SnapshotStatus::None,
+        }
+    }
+}
+
+const COMPLETED_SNAPSHOTS_PATH: &str = "./data/out/snapshots.json";
+
+pub fn get_snapshot_status(link_node: &mut Individual) -> SnapshotStatus {
+    SnapshotStatus::from(link_node.get_first_literal("cfg:snapshot_status").unwrap_or_default().as_str())
+}
+
+// выгрузка запрошена или выполняется
+pub fn is_snapshot_required(link_node: &mut Individual) -> bool {
+    let status = get_snapshot_status(link_node);
+    status == SnapshotStatus::Required || status == SnapshotStatus::InProgress
+}
+
+// связанные ноды, для которых запрошена полная выгрузка
+pub fn get_nodes_required_snapshot(backend: &mut Backend) -> Vec<Individual> {
+    let mut res = vec![];
+    for mut link_node in get_linked_nodes(backend) {
+        // in_progress означает что выгрузка выполняется либо была прервана, продолжаем ее
+        if is_snapshot_required(&mut link_node) {
+            res.push(link_node);
+        }
+    }
+    res
+}
+
+// время установки статуса (cfg:snapshot_date)
+pub fn get_snapshot_date(link_node: &mut Individual) -> Option<i64> {
+    link_node.get_first_integer("cfg:snapshot_date")
+}
+
+// позиция выполняемой выгрузки: номер запроса и начало очередной порции его результатов
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct SnapshotPosition {
+    pub query_index: usize,
+    pub from: i32,
+}
+
+pub fn get_snapshot_position(link_node: &mut Individual) -> SnapshotPosition {
+    SnapshotPosition {
+        query_index: link_node.get_first_integer("cfg:snapshot_query_index").unwrap_or_default().max(0) as usize,
+        from: link_node.get_first_integer("cfg:snapshot_from").unwrap_or_default().max(0) as i32,
+    }
+}
+
+// false - позиция не записана, после перезапуска выгрузка продолжится с ранее записанной позиции
+pub fn set_snapshot_position(backend: &mut Backend, systicket: &str, link_node_id: &str, pos: &SnapshotPosition) -> bool {
+    let mut indv = Individual::default();
+    indv.set_id(link_node_id);
+    indv.add_integer("cfg:snapshot_query_index", pos.query_index as i64);
+    indv.add_integer("cfg:snapshot_from", pos.from as i64);
+
+    let res = backend.mstorage_api.update(systicket, IndvOp::SetIn, &indv);
+    if res.result != ResultCode::Ok {
+        error!("fail update snapshot position, uri={}, result_code={:?}", link_node_id, res.result);
+        return false;
+    }
+    true
+}
+
+// выполненные выгрузки: id v-s:LinkedNode -> cfg:snapshot_date статуса in_progress этой выгрузки
+pub fn load_completed_snapshots() -> HashMap<String, i64> {
+    File::open(COMPLETED_SNAPSHOTS_PATH).ok().and_then(|f| serde_json::from_reader(f).ok()).unwrap_or_default()
+}
+
+pub fn store_completed_snapshots(completed: &HashMap<String, i64>) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", COMPLETED_SNAPSHOTS_PATH);
+    let mut f = File::create(&tmp_path)?;
+    f.write_all(serde_json::to_string(completed)?.as_bytes())?;
+    f.sync_all()?;
+    rename(&tmp_path, COMPLETED_SNAPSHOTS_PATH)
+}
+
+// возвращает записанное время установки статуса, None - статус не записан
+pub fn set_snapshot_status(backend: &mut Backend, systicket: &str, link_node_id: &str, status: SnapshotStatus) -> Option<i64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default();
+
+    let mut indv = Individual::default();
+    indv.set_id(link_node_id);
+    indv.add_string("cfg:snapshot_status", status.as_str(), Lang::none());
+    indv.add_integer("cfg:snapshot_date", now);
+
+    let res = backend.mstorage_api.update(systicket, IndvOp::SetIn, &indv);
+    if res.result != ResultCode::Ok {
+        error!("fail update snapshot status, uri={}, result_code={:?}", link_node_id, res.result);
+        return None;
+    }
+    info!("snapshot status of {} = {}", link_node_id, status.as_str());
+    Some(now)
+}
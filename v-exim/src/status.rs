@@ -0,0 +1,194 @@
+/*
+ * Состояние обмена со связанной нодой: индивид <id связанной ноды>_<модуль>_status
+ * (v-s:LinkedNodeStatus), например cfg:veda_ex1_inquire_status. veda-exim-inquire и
+ * veda-exim-respond ведут отдельные индивиды, доступные для просмотра и поиска в Veda
+ */
+use crate::circuit_breaker::CircuitState;
+use crate::ExImCode;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use v_common::module::veda_backend::Backend;
+use v_common::onto::datatype::Lang;
+use v_common::onto::individual::Individual;
+use v_common::v_api::api_client::{IndvOp, MStorageClient};
+use v_common::v_api::obj::ResultCode;
+use v_queue::consumer::Consumer;
+
+// как часто записываются изменения счетчиков и времени обмена (мс), ошибки и смена состояния автомата защиты записываются сразу
+const STATUS_STORE_INTERVAL: u64 = 10000;
+
+// состояния нод, которые обслуживает veda-exim-respond: node_id -> состояние
+pub type NodeStatuses = Arc<Mutex<HashMap<String, NodeStatus>>>;
+
+// модуль, ведущий состояние; счетчики и ошибки модулей записываются в разные индивиды
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StatusModule {
+    Inquire,
+    Respond,
+}
+
+impl StatusModule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatusModule::Inquire => "inquire",
+            StatusModule::Respond => "respond",
+        }
+    }
+}
+
+pub fn get_status_id(link_node_id: &str, module: StatusModule) -> String {
+    format!("{}_{}_status", link_node_id, module.as_str())
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
+// отставание потребителя в текущей части очереди
+pub fn get_queue_lag(queue_consumer: &mut Consumer) -> i64 {
+    if let Err(e) = queue_consumer.queue.get_info_of_part(queue_consumer.id, true) {
+        error!("get_info_of_part {}: {}", queue_consumer.id, e.as_str());
+    }
+    (queue_consumer.queue.count_pushed - queue_consumer.count_popped) as i64
+}
+
+pub struct NodeStatus {
+    module: StatusModule,
+    link_node_id: String,
+    node_id: String,
+    // последняя успешная отправка изменений ноде и прием изменений от нее
+    last_push: Option<i64>,
+    last_pull: Option<i64>,
+    count_sent: i64,
+    count_recv: i64,
+    queue_lag: Option<i64>,
+    last_error: Option<ExImCode>,
+    last_error_date: Option<i64>,
+    circuit: Option<CircuitState>,
+    changed: bool,
+    urgent: bool,
+    stored_at: Instant,
+}
+
+impl NodeStatus {
+    // счетчики продолжаются с сохраненных значений
+    pub fn load(backend: &mut Backend, module: StatusModule, link_node_id: &str, node_id: &str) -> Self {
+        let mut status = NodeStatus {
+            module,
+            link_node_id: link_node_id.to_owned(),
+            node_id: node_id.to_owned(),
+            last_push: None,
+            last_pull: None,
+            count_sent: 0,
+            count_recv: 0,
+            queue_lag: None,
+            last_error: None,
+            last_error_date: None,
+            circuit: None,
+            changed: false,
+            urgent: false,
+            stored_at: Instant::now(),
+        };
+
+        let mut indv = Individual::default();
+        if backend.storage.get_individual(&get_status_id(link_node_id, module), &mut indv) {
+            status.count_sent = indv.get_first_integer("cfg:count_sent").unwrap_or(0);
+            status.count_recv = indv.get_first_integer("cfg:count_recv").unwrap_or(0);
+        }
+        status
+    }
+
+    pub fn on_push(&mut self, count_sent: i32) {
+        self.last_push = Some(now());
+        self.count_sent += count_sent as i64;
+        self.changed = true;
+    }
+
+    pub fn on_pull(&mut self, count_recv: i32) {
+        self.last_pull = Some(now());
+        self.count_recv += count_recv as i64;
+        self.changed = true;
+    }
+
+    pub fn set_queue_lag(&mut self, lag: i64) {
+        if self.queue_lag != Some(lag) {
+            self.queue_lag = Some(lag);
+            self.changed = true;
+        }
+    }
+
+    // повторение той же ошибки записывается вместе с остальными изменениями
+    pub fn on_error(&mut self, code: ExImCode) {
+        if self.last_error.as_ref() != Some(&code) {
+            self.urgent = true;
+        }
+        self.last_error = Some(code);
+        self.last_error_date = Some(now());
+        self.changed = true;
+    }
+
+    pub fn set_circuit_state(&mut self, state: CircuitState) {
+        if self.circuit != Some(state) {
+            self.circuit = Some(state);
+            self.changed = true;
+            self.urgent = true;
+        }
+    }
+
+    pub fn store_if_needed(&mut self, mstorage: &mut MStorageClient, sys_ticket: &str) {
+        if let Some(indv) = self.take_if_needed() {
+            store_status(mstorage, sys_ticket, &indv);
+        }
+    }
+
+    // индивид состояния, если его пора записать; записывает вызывающий, например вне блокировки общего списка состояний
+    pub fn take_if_needed(&mut self) -> Option<Individual> {
+        if self.urgent || (self.changed && self.stored_at.elapsed() >= Duration::from_millis(STATUS_STORE_INTERVAL)) {
+            Some(self.take())
+        } else {
+            None
+        }
+    }
+
+    pub fn take(&mut self) -> Individual {
+        let mut indv = Individual::default();
+        indv.set_id(&get_status_id(&self.link_node_id, self.module));
+        indv.add_uri("rdf:type", "v-s:LinkedNodeStatus");
+        indv.add_uri("cfg:linked_node", &self.link_node_id);
+        indv.add_string("cfg:node_id", &self.node_id, Lang::none());
+        indv.add_string("cfg:exim_module", self.module.as_str(), Lang::none());
+        indv.add_integer("cfg:count_sent", self.count_sent);
+        indv.add_integer("cfg:count_recv", self.count_recv);
+        if let Some(d) = self.last_push {
+            indv.add_datetime("cfg:last_push_date", d);
+        }
+        if let Some(d) = self.last_pull {
+            indv.add_datetime("cfg:last_pull_date", d);
+        }
+        if let Some(lag) = self.queue_lag {
+            indv.add_integer("cfg:queue_lag", lag);
+        }
+        if let (Some(code), Some(d)) = (&self.last_error, self.last_error_date) {
+            indv.add_string("cfg:last_error", &code.as_string(), Lang::none());
+            indv.add_datetime("cfg:last_error_date", d);
+        }
+        if let Some(state) = self.circuit {
+            indv.add_string("cfg:circuit_state", state.as_str(), Lang::none());
+        }
+
+        self.stored_at = Instant::now();
+        self.changed = false;
+        self.urgent = false;
+        indv
+    }
+}
+
+pub fn store_status(mstorage: &mut MStorageClient, sys_ticket: &str, indv: &Individual) -> bool {
+    let res = mstorage.update(sys_ticket, IndvOp::SetIn, indv);
+    if res.result != ResultCode::Ok {
+        error!("fail update status {}, result_code={:?}", indv.get_id(), res.result);
+        return false;
+    }
+    true
+}
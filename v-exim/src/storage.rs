@@ -0,0 +1,222 @@
+/*
+ * Запись принятых изменений: индивиды, содержимое файлов и сообщения для
+ * пересылки другим нодам. Реализации:
+ * MStorageClient (модуль main_module_url и каталог data/files) и MemoryStorage
+ * (в памяти, для проверки приема без запущенной Veda)
+ */
+use crate::message::ExImMessage;
+use crate::relay::put_relay_message;
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{self, Write};
+use v_common::onto::individual::{Individual, RawObj};
+use v_common::onto::individual2msgpack::to_msgpack;
+use v_common::onto::parser::parse_raw;
+use v_common::onto::resource::Resource;
+use v_common::v_api::api_client::{IndvOp, MStorageClient, ALL_MODULES};
+use v_common::v_api::obj::ResultCode;
+
+pub trait ImportStorage {
+    fn update_individual(&mut self, systicket: &str, cmd: IndvOp, indv: &Individual) -> Result<(), ResultCode>;
+
+    // dir_path - значение v-s:filePath, file_name - v-s:fileUri
+    fn store_file(&mut self, dir_path: &str, file_name: &str, data: &[u8]) -> io::Result<()>;
+
+    // сообщение, которое эта нода пересылает дальше как транзитная
+    fn relay_message(&mut self, msg: &ExImMessage) -> io::Result<()>;
+}
+
+impl ImportStorage for MStorageClient {
+    fn update_individual(&mut self, systicket: &str, cmd: IndvOp, indv: &Individual) -> Result<(), ResultCode> {
+        match self.update_use_param(systicket, "exim", "?", ALL_MODULES, cmd, indv) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.result),
+        }
+    }
+
+    fn store_file(&mut self, dir_path: &str, file_name: &str, data: &[u8]) -> io::Result<()> {
+        let src_dir_path = "data/files".to_owned() + dir_path;
+        create_dir_all(&src_dir_path)?;
+
+        let src_full_path = src_dir_path + "/" + file_name;
+        File::create(&src_full_path)?.write_all(data)?;
+        info!("success create file {}", src_full_path);
+        Ok(())
+    }
+
+    fn relay_message(&mut self, msg: &ExImMessage) -> io::Result<()> {
+        put_relay_message(msg)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    // текущее состояние индивидов после применения изменений
+    pub individuals: HashMap<String, Individual>,
+    // все выполненные изменения в порядке поступления
+    pub updates: Vec<(IndvOp, Individual)>,
+    // путь (v-s:filePath/v-s:fileUri) -> содержимое
+    pub files: HashMap<String, Vec<u8>>,
+    // сообщения для пересылки в порядке поступления
+    pub relayed: Vec<ExImMessage>,
+    // изменения не записываются (сбой хранилища)
+    pub fail_update: bool,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+fn copy_individual(indv: &Individual) -> Result<Individual, ResultCode> {
+    let mut raw = vec![];
+    to_msgpack(indv, &mut raw).map_err(|_| ResultCode::InternalServerError)?;
+
+    let mut copy = Individual::new_raw(RawObj::new(raw));
+    parse_raw(&mut copy).map_err(|_| ResultCode::InternalServerError)?;
+    copy.parse_all();
+    Ok(copy)
+}
+
+// состояние индивида после команды cmd: Put заменяет индивид, AddTo добавляет отсутствующие значения
+// предикатов, RemoveFrom удаляет указанные значения, SetIn заменяет значения указанных предикатов
+fn apply_cmd(prev_state: Option<&Individual>, cmd: &IndvOp, indv: &Individual) -> Result<Option<Individual>, ResultCode> {
+    let mut patch = copy_individual(indv)?;
+    let mut state = match (cmd, prev_state) {
+        (IndvOp::Put, _) => return Ok(Some(patch)),
+        (IndvOp::Remove, _) => return Ok(None),
+        // удалять значения не из чего
+        (IndvOp::RemoveFrom, None) => return Ok(None),
+        (_, Some(prev)) => copy_individual(prev)?,
+        (_, None) => {
+            let mut state = Individual::default();
+            state.set_id(indv.get_id());
+            state
+        },
+    };
+
+    for predicate in patch.get_predicates() {
+        let values = patch.get_resources(&predicate).unwrap_or_default();
+        let prev_values = state.get_resources(&predicate).unwrap_or_default();
+
+        let new_values: Vec<Resource> = match cmd {
+            IndvOp::AddTo => prev_values.iter().cloned().chain(values.into_iter().filter(|r| !prev_values.contains(r))).collect(),
+            IndvOp::RemoveFrom => prev_values.into_iter().filter(|r| !values.contains(r)).collect(),
+            IndvOp::SetIn => values,
+            _ => return Err(ResultCode::BadRequest),
+        };
+
+        state.remove(&predicate);
+        if !new_values.is_empty() {
+            state.set_resources(&predicate, &new_values);
+        }
+    }
+    Ok(Some(state))
+}
+
+impl ImportStorage for MemoryStorage {
+    fn update_individual(&mut self, _systicket: &str, cmd: IndvOp, indv: &Individual) -> Result<(), ResultCode> {
+        if self.fail_update {
+            return Err(ResultCode::InternalServerError);
+        }
+        match apply_cmd(self.individuals.get(indv.get_id()), &cmd, indv)? {
+            Some(state) => {
+                self.individuals.insert(indv.get_id().to_owned(), state);
+            },
+            None => {
+                self.individuals.remove(indv.get_id());
+            },
+        }
+        self.updates.push((cmd, copy_individual(indv)?));
+        Ok(())
+    }
+
+    fn store_file(&mut self, dir_path: &str, file_name: &str, data: &[u8]) -> io::Result<()> {
+        self.files.insert(format!("{}/{}", dir_path, file_name), data.to_vec());
+        Ok(())
+    }
+
+    fn relay_message(&mut self, msg: &ExImMessage) -> io::Result<()> {
+        self.relayed.push(msg.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use v_common::onto::datatype::Lang;
+
+    fn labels(storage: &mut MemoryStorage, id: &str) -> Vec<String> {
+        let mut labels = storage.individuals.get_mut(id).and_then(|i| i.get_literals("rdfs:label")).unwrap_or_default();
+        labels.sort();
+        labels
+    }
+
+    fn patch(id: &str, labels: &[&str]) -> Individual {
+        let mut indv = Individual::default();
+        indv.set_id(id);
+        for label in labels {
+            indv.add_string("rdfs:label", label, Lang::none());
+        }
+        indv
+    }
+
+    #[test]
+    fn add_to_appends_missing_values() {
+        let mut storage = MemoryStorage::new();
+        storage.update_individual("", IndvOp::Put, &patch("d:a", &["a"])).unwrap();
+        storage.update_individual("", IndvOp::AddTo, &patch("d:a", &["a", "b"])).unwrap();
+
+        assert_eq!(labels(&mut storage, "d:a"), vec!["a", "b"]);
+        assert_eq!(storage.updates.len(), 2);
+    }
+
+    #[test]
+    fn add_to_creates_missing_individual() {
+        let mut storage = MemoryStorage::new();
+        storage.update_individual("", IndvOp::AddTo, &patch("d:a", &["a"])).unwrap();
+
+        assert_eq!(labels(&mut storage, "d:a"), vec!["a"]);
+    }
+
+    #[test]
+    fn remove_from_removes_given_values() {
+        let mut storage = MemoryStorage::new();
+        storage.update_individual("", IndvOp::Put, &patch("d:a", &["a", "b", "c"])).unwrap();
+        storage.update_individual("", IndvOp::RemoveFrom, &patch("d:a", &["b", "x"])).unwrap();
+        assert_eq!(labels(&mut storage, "d:a"), vec!["a", "c"]);
+
+        // предикат без значений удаляется
+        storage.update_individual("", IndvOp::RemoveFrom, &patch("d:a", &["a", "c"])).unwrap();
+        assert!(labels(&mut storage, "d:a").is_empty());
+        assert!(storage.individuals.contains_key("d:a"));
+
+        storage.update_individual("", IndvOp::RemoveFrom, &patch("d:b", &["a"])).unwrap();
+        assert!(!storage.individuals.contains_key("d:b"));
+    }
+
+    #[test]
+    fn set_in_replaces_values_of_given_predicates() {
+        let mut storage = MemoryStorage::new();
+        let mut indv = patch("d:a", &["a", "b"]);
+        indv.add_uri("rdf:type", "v-s:Document");
+        storage.update_individual("", IndvOp::Put, &indv).unwrap();
+        storage.update_individual("", IndvOp::SetIn, &patch("d:a", &["c"])).unwrap();
+
+        assert_eq!(labels(&mut storage, "d:a"), vec!["c"]);
+        assert_eq!(storage.individuals.get_mut("d:a").and_then(|i| i.get_first_literal("rdf:type")), Some("v-s:Document".to_owned()));
+    }
+
+    #[test]
+    fn put_and_remove_replace_state() {
+        let mut storage = MemoryStorage::new();
+        storage.update_individual("", IndvOp::Put, &patch("d:a", &["a"])).unwrap();
+        storage.update_individual("", IndvOp::Put, &patch("d:a", &["b"])).unwrap();
+        assert_eq!(labels(&mut storage, "d:a"), vec!["b"]);
+
+        storage.update_individual("", IndvOp::Remove, &patch("d:a", &[])).unwrap();
+        assert!(!storage.individuals.contains_key("d:a"));
+    }
+}
@@ -0,0 +1,221 @@
+/*
+ * Транспорт обмена с нодой. Выбирается по схеме адреса связанной ноды (rdf:value):
+ * http:// - HTTP/JSON, tcp:// - nanomsg/NNG в режиме req-rep.
+ *
+ * Принимаемые от ноды сообщения (pull_batch) остаются у нее до подтверждения (ack)
+ * по позициям, неподтвержденные сообщения выдаются повторно
+ */
+use crate::async_client::{shared_client, shared_runtime, AsyncHttpClient};
+use crate::{decode_message, ExImCode, IOResult};
+use nng::options::{Options, RecvTimeout, SendTimeout};
+use nng::{Message, Protocol, Socket};
+use serde_json::json;
+use serde_json::value::Value as JSONValue;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// время ожидания ответа NNG ноды на обычный запрос
+const NNG_TIMEOUT: u64 = 30000;
+
+pub trait Transport {
+    fn addr(&self) -> &str;
+
+    // идентификатор ноды (node_id), отвечающей по этому адресу
+    fn hello(&self) -> Result<String, Box<dyn Error>>;
+
+    // передает ноде сообщения в формате encode_message, возвращает результат приема каждого
+    fn send_batch(&self, msgs: &[JSONValue]) -> Result<Vec<IOResult>, Box<dyn Error>>;
+
+    // запрашивает у ноды не более max_count сообщений для importer_id,
+    // wait - сколько миллисекунд нода может удерживать запрос в ожидании новых изменений, 0 - ответ сразу
+    fn pull_batch(&self, importer_id: &str, max_count: usize, wait: u64) -> Result<Vec<JSONValue>, Box<dyn Error>>;
+
+    // подтверждает прием сообщений с указанными позициями (поле pos сообщений порции)
+    fn ack(&self, importer_id: &str, positions: &[u64]) -> Result<(), Box<dyn Error>>;
+}
+
+pub fn create_transport(addr: &str) -> Result<Box<dyn Transport>, Box<dyn Error>> {
+    if addr.starts_with("tcp://") {
+        Ok(Box::new(NngTransport::new(addr)?))
+    } else {
+        Ok(Box::new(HttpTransport::new(addr)))
+    }
+}
+
+// блокирующая обертка над AsyncHttpClient для вызова вне async кода, запросы выполняются
+// в общем runtime общим клиентом ноды, поэтому создание транспорта не открывает новых соединений
+pub struct HttpTransport {
+    client: Arc<AsyncHttpClient>,
+}
+
+impl HttpTransport {
+    pub fn new(addr: &str) -> Self {
+        HttpTransport {
+            client: shared_client(addr),
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn addr(&self) -> &str {
+        self.client.addr()
+    }
+
+    fn hello(&self) -> Result<String, Box<dyn Error>> {
+        Ok(shared_runtime().block_on(self.client.hello())?)
+    }
+
+    fn send_batch(&self, msgs: &[JSONValue]) -> Result<Vec<IOResult>, Box<dyn Error>> {
+        Ok(shared_runtime().block_on(self.client.send_batch(msgs))?)
+    }
+
+    fn pull_batch(&self, importer_id: &str, max_count: usize, wait: u64) -> Result<Vec<JSONValue>, Box<dyn Error>> {
+        Ok(shared_runtime().block_on(self.client.pull_batch(importer_id, max_count, wait))?)
+    }
+
+    fn ack(&self, importer_id: &str, positions: &[u64]) -> Result<(), Box<dyn Error>> {
+        Ok(shared_runtime().block_on(self.client.ack(importer_id, positions))?)
+    }
+}
+
+// запрос NNG - JSON документ с полем cmd:
+// {"cmd": "hello"}                                           -> {"node_id": ...}
+// {"cmd": "import_batch", "msgs": [...]}                     -> [IOResult, ...]
+// {"cmd": "export_batch", "node_id": <importer_id>, "max": n} -> {"msgs": [...]}
+// {"cmd": "ack", "node_id": <importer_id>, "positions": [...]} -> {}
+pub struct NngTransport {
+    addr: String,
+    soc: Socket,
+}
+
+impl NngTransport {
+    pub fn new(addr: &str) -> Result<Self, Box<dyn Error>> {
+        let soc = Socket::new(Protocol::Req0)?;
+        soc.set_opt::<SendTimeout>(Some(Duration::from_millis(NNG_TIMEOUT)))?;
+        soc.dial_async(addr)?;
+
+        Ok(NngTransport {
+            addr: addr.to_owned(),
+            soc,
+        })
+    }
+
+    fn request(&self, req: &JSONValue, timeout: u64) -> Result<JSONValue, Box<dyn Error>> {
+        self.soc.set_opt::<RecvTimeout>(Some(Duration::from_millis(timeout)))?;
+        self.soc.send(Message::from(req.to_string().as_bytes())).map_err(|(_, e)| e)?;
+        let reply = self.soc.recv()?;
+        Ok(serde_json::from_slice(&reply)?)
+    }
+}
+
+impl Transport for NngTransport {
+    fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    fn hello(&self) -> Result<String, Box<dyn Error>> {
+        let res = self.request(&json!({"cmd": "hello"}), NNG_TIMEOUT)?;
+        Ok(res["node_id"].as_str().unwrap_or_default().to_owned())
+    }
+
+    fn send_batch(&self, msgs: &[JSONValue]) -> Result<Vec<IOResult>, Box<dyn Error>> {
+        let res = self.request(&json!({"cmd": "import_batch", "msgs": msgs}), NNG_TIMEOUT)?;
+        Ok(serde_json::from_value(res)?)
+    }
+
+    // запросы NNG обслуживаются нодой последовательно, поэтому ожидание изменений (wait) не передается
+    fn pull_batch(&self, importer_id: &str, max_count: usize, _wait: u64) -> Result<Vec<JSONValue>, Box<dyn Error>> {
+        let res = self.request(&json!({"cmd": "export_batch", "node_id": importer_id, "max": max_count}), NNG_TIMEOUT)?;
+        Ok(res["msgs"].as_array().cloned().unwrap_or_default())
+    }
+
+    fn ack(&self, importer_id: &str, positions: &[u64]) -> Result<(), Box<dyn Error>> {
+        self.request(&json!({"cmd": "ack", "node_id": importer_id, "positions": positions}), NNG_TIMEOUT)?;
+        Ok(())
+    }
+}
+
+// транспорт в памяти, позволяет проверять логику обмена без сети и второй ноды
+pub struct MemoryTransport {
+    node_id: String,
+    // сообщения, переданные ноде (send_batch)
+    received: Mutex<Vec<JSONValue>>,
+    // сообщения, которые нода выдает по запросу (pull_batch) с позицией, удаляются после подтверждения
+    outgoing: Mutex<VecDeque<(u64, JSONValue)>>,
+    next_pos: AtomicU64,
+    // подтверждение завершается сбоем, сообщения остаются у ноды
+    fail_ack: AtomicBool,
+}
+
+impl MemoryTransport {
+    pub fn new(node_id: &str) -> Self {
+        MemoryTransport {
+            node_id: node_id.to_owned(),
+            received: Mutex::new(vec![]),
+            outgoing: Mutex::new(VecDeque::new()),
+            next_pos: AtomicU64::new(0),
+            fail_ack: AtomicBool::new(false),
+        }
+    }
+
+    pub fn push_outgoing(&self, msg: JSONValue) {
+        let pos = self.next_pos.fetch_add(1, Ordering::SeqCst);
+        self.outgoing.lock().unwrap().push_back((pos, msg));
+    }
+
+    pub fn count_outgoing(&self) -> usize {
+        self.outgoing.lock().unwrap().len()
+    }
+
+    pub fn set_fail_ack(&self, fail: bool) {
+        self.fail_ack.store(fail, Ordering::SeqCst);
+    }
+
+    pub fn take_received(&self) -> Vec<JSONValue> {
+        std::mem::take(&mut *self.received.lock().unwrap())
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn addr(&self) -> &str {
+        "memory"
+    }
+
+    fn hello(&self) -> Result<String, Box<dyn Error>> {
+        Ok(self.node_id.clone())
+    }
+
+    fn send_batch(&self, msgs: &[JSONValue]) -> Result<Vec<IOResult>, Box<dyn Error>> {
+        let mut results = vec![];
+        for msg in msgs {
+            let indv = decode_message(msg)?;
+            results.push(IOResult::new(indv.get_id(), ExImCode::Ok));
+        }
+        self.received.lock().unwrap().extend_from_slice(msgs);
+        Ok(results)
+    }
+
+    fn pull_batch(&self, _importer_id: &str, max_count: usize, _wait: u64) -> Result<Vec<JSONValue>, Box<dyn Error>> {
+        let outgoing = self.outgoing.lock().unwrap();
+        Ok(outgoing
+            .iter()
+            .take(max_count)
+            .map(|(pos, msg)| {
+                let mut msg = msg.clone();
+                msg["pos"] = json!(pos);
+                msg
+            })
+            .collect())
+    }
+
+    fn ack(&self, _importer_id: &str, positions: &[u64]) -> Result<(), Box<dyn Error>> {
+        if self.fail_ack.load(Ordering::SeqCst) {
+            return Err("ack failed".into());
+        }
+        self.outgoing.lock().unwrap().retain(|(pos, _)| !positions.contains(pos));
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+/*
+ * Обслуживание обмена.
+ *
+ * dlq list [<consumer>]
+ *      список очередей недоставленных сообщений либо сообщения очереди потребителя (i_<node>, r_<node>)
+ * dlq show <consumer> <id>
+ *      описание недоставленного сообщения и его содержимое
+ * dlq retry <consumer> <id>|--all
+ *      повторная отправка ноде, принятое нодой сообщение удаляется из очереди недоставленных;
+ *      сообщения, принятые от ноды (in_<consumer>), повторно записываются на этой ноде
+ * dlq discard <consumer> <id>|--all
+ *      удаление недоставленных сообщений
+ * queue parts
+ *      части очереди ./data/out/extract и число сообщений в них
+ * queue consumers
+ *      потребители очереди, их позиции и отставание
+ * queue dump [--part <id>] [--pos <n>] [--count <n>]
+ *      сообщения очереди в виде JSON
+ * consumer rewind <consumer> --part <id> [--pos <n>] | --date <date>
+ *      перемещение потребителя на позицию части либо на первое сообщение не ранее даты
+ * consumer reset <consumer>
+ *      перемещение потребителя в конец очереди
+ * consumer delete <consumer>
+ *      удаление потребителя удаленной ноды
+ * replay --node <node_id> --from <date>
+ *      повторная отправка ноде изменений с даты через временного потребителя t_<node>_<date>
+ * purge [--retention-days <n>] [--archive <dir>] [--dry-run]
+ *      удаление (перенос в архив) частей очереди, прочитанных всеми потребителями связанных нод
+ *
+ * Перемещать и удалять потребителей можно только при остановленных модулях обмена
+ */
+#[macro_use]
+extern crate log;
+
+mod purge;
+mod queue;
+mod replay;
+
+use serde_json::json;
+use std::error::Error;
+use v_common::module::module_impl::init_log;
+use v_common::module::veda_backend::Backend;
+use v_common::storage::common::StorageMode;
+use v_exim::dead_letter::{DeadLetter, DeadLetterQueue};
+use v_exim::linked_node::LinkedNode;
+use v_exim::transport::create_transport;
+use v_exim::*;
+
+fn main() {
+    init_log("EXIM_ADMIN");
+
+    let args: Vec<String> = std::env::args().collect();
+
+    let res = match args.get(1).map(|s| s.as_str()) {
+        Some("dlq") => dlq_command(&args[2..]),
+        Some("queue") => queue::queue_command(&args[2..]),
+        Some("consumer") => queue::consumer_command(&args[2..]),
+        Some("replay") => replay::replay_command(&args[2..]),
+        Some("purge") => purge::purge_command(&args[2..]),
+        _ => Err(usage().into()),
+    };
+
+    if let Err(e) = res {
+        error!("{}", e);
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn usage() -> &'static str {
+    "usage: veda-exim-admin
+    dlq list [<consumer>] | show <consumer> <id> | retry <consumer> <id>|--all | discard <consumer> <id>|--all
+    queue parts | consumers | dump [--part <id>] [--pos <n>] [--count <n>]
+    consumer rewind <consumer> --part <id> [--pos <n>] | --date <date>
+    consumer reset <consumer>
+    consumer delete <consumer>
+    replay --node <node_id> --from <date>
+    purge [--retention-days <n>] [--archive <dir>] [--dry-run]"
+}
+
+fn dlq_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let cmd = args.first().map(|s| s.as_str());
+    let consumer = args.get(1).map(|s| s.as_str());
+    let id = args.get(2).map(|s| s.as_str());
+
+    match (cmd, consumer, id) {
+        (Some("list"), None, _) => {
+            for consumer in DeadLetterQueue::list_consumers() {
+                println!("{}\t{}", consumer, DeadLetterQueue::new(&consumer).list().len());
+            }
+            Ok(())
+        },
+        (Some("list"), Some(consumer), _) => {
+            for letter in DeadLetterQueue::new(consumer).list() {
+                println!("{}\t{}\t{:?}\t{}\t{}", letter.id, letter.msg_id, letter.error, letter.failures, letter.detail.as_deref().unwrap_or_default());
+            }
+            Ok(())
+        },
+        (Some("show"), Some(consumer), Some(id)) => {
+            let letter = DeadLetterQueue::new(consumer).get(id).ok_or(format!("not found dead letter {}", id))?;
+            println!("{}", serde_json::to_string_pretty(&describe(&letter))?);
+            Ok(())
+        },
+        (Some("retry"), Some(consumer), Some(id)) => retry(consumer, id),
+        (Some("discard"), Some(consumer), Some(id)) => {
+            let dlq = DeadLetterQueue::new(consumer);
+            for letter in select(&dlq, id)? {
+                dlq.remove(&letter.id)?;
+                info!("discard dead letter {}/{}, msg={}", consumer, letter.id, letter.msg_id);
+            }
+            Ok(())
+        },
+        _ => Err(usage().into()),
+    }
+}
+
+fn select(dlq: &DeadLetterQueue, id: &str) -> Result<Vec<DeadLetter>, Box<dyn Error>> {
+    if id == "--all" {
+        Ok(dlq.list())
+    } else {
+        Ok(vec![dlq.get(id).ok_or(format!("not found dead letter {}", id))?])
+    }
+}
+
+// описание с раскрытым содержимым сообщения
+fn describe(letter: &DeadLetter) -> serde_json::Value {
+    let (content, new_state) = match decode_message(&letter.msg) {
+        Ok(mut indv) => queue::message_json(&mut indv),
+        Err(e) => (json!(format!("fail decode message: {}", e)), None),
+    };
+
+    json!({
+        "id": letter.id,
+        "node_id": letter.node_id,
+        "msg_id": letter.msg_id,
+        "error": letter.error,
+        "detail": letter.detail,
+        "failures": letter.failures,
+        "date": letter.date,
+        "invalid": letter.invalid,
+        "content": content,
+        "new_state": new_state,
+    })
+}
+
+fn retry(consumer: &str, id: &str) -> Result<(), Box<dyn Error>> {
+    let dlq = DeadLetterQueue::new(consumer);
+    let letters: Vec<DeadLetter> = select(&dlq, id)?.into_iter().filter(|l| !l.invalid).collect();
+    if letters.is_empty() {
+        return Err(format!("no dead letters to retry in {}, invalid queue elements can only be discarded", consumer).into());
+    }
+
+    let mut backend = Backend::create(StorageMode::ReadOnly, false);
+    let linked_nodes: Vec<LinkedNode> = get_linked_nodes(&mut backend).iter_mut().filter_map(LinkedNode::new).collect();
+
+    let mut count_failed = 0;
+    for letter in letters {
+        if letter.received {
+            if apply_received(&mut backend, &letter)? {
+                dlq.remove(&letter.id)?;
+                info!("success apply dead letter {}/{}, msg={}", consumer, letter.id, letter.msg_id);
+            } else {
+                count_failed += 1;
+            }
+            continue;
+        }
+
+        let node = linked_nodes.iter().find(|n| n.node_id == letter.node_id).ok_or(format!("not found linked node {}", letter.node_id))?;
+        let transport = create_transport(&node.addr)?;
+
+        let res = transport.send_batch(std::slice::from_ref(&letter.msg))?.pop().ok_or("empty response")?;
+        if res.res_code == ExImCode::Ok {
+            dlq.remove(&letter.id)?;
+            info!("success retry dead letter {}/{}, msg={}", consumer, letter.id, letter.msg_id);
+        } else {
+            error!("node {} fail accept dead letter {}, err={:?}, detail={}", node.node_id, letter.id, res.res_code, res.detail.as_deref().unwrap_or_default());
+            count_failed += 1;
+        }
+    }
+
+    if count_failed > 0 {
+        return Err(format!("{} dead letters not accepted", count_failed).into());
+    }
+    Ok(())
+}
+
+// сообщение, принятое от ноды, но не записанное, записывается на этой ноде
+fn apply_received(backend: &mut Backend, letter: &DeadLetter) -> Result<bool, Box<dyn Error>> {
+    let sys_ticket = backend.get_sys_ticket_id().map_err(|_| "fail get system ticket")?;
+    let my_node_id = get_db_id(backend).ok_or("not found node id (cfg:system)")?;
+    let my_groups = get_node_group_membership(backend, &my_node_id);
+
+    let mut recv_indv = decode_message(&letter.msg)?;
+    let res = processing_imported_message(&my_node_id, &my_groups, &mut recv_indv, &sys_ticket, &mut backend.mstorage_api);
+    if res.res_code != ExImCode::Ok {
+        error!("fail apply dead letter {}, err={:?}, detail={}", letter.id, res.res_code, res.detail.as_deref().unwrap_or_default());
+        return Ok(false);
+    }
+    Ok(true)
+}
@@ -0,0 +1,82 @@
+/*
+ * Удаление частей очереди ./data/out/extract, прочитанных всеми потребителями
+ * связанных нод и хранящихся дольше срока хранения (exim_queue_retention_days).
+ * Нода, для которой еще нет потребителя, может нуждаться во всей очереди,
+ * поэтому в этом случае части не удаляются
+ */
+use crate::queue::{get_arg, get_num_arg, is_orphan_dump_consumer};
+use std::error::Error;
+use std::time::{Duration, SystemTime};
+use v_common::module::veda_backend::Backend;
+use v_common::storage::common::StorageMode;
+use v_exim::linked_node::LinkedNode;
+use v_exim::queue_tools::*;
+use v_exim::*;
+
+pub fn purge_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let archive = get_arg(args, "--archive");
+    let retention_days = get_num_arg(args, "--retention-days")?.map(u64::from).unwrap_or_else(get_queue_retention_days);
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+
+    let mut backend = Backend::create(StorageMode::ReadOnly, false);
+    let nodes: Vec<LinkedNode> = get_linked_nodes(&mut backend).iter_mut().filter_map(LinkedNode::new).collect();
+    // потребитель, из которого нода получает изменения в текущем режиме обмена
+    let node_consumers: Vec<String> = nodes.iter().map(get_node_consumer_name).collect();
+    // потребители связанных нод для других режимов обмена (остались после смены cfg:exim_mode)
+    let stale_consumers: Vec<String> =
+        nodes.iter().flat_map(|n| ["i", "r"].iter().map(move |p| format!("{}_{}", p, n.node_id.replace(':', "_")))).filter(|c| !node_consumers.contains(c)).collect();
+
+    let missing: Vec<String> = node_consumers.iter().filter(|c| !is_consumer_exists(c)).cloned().collect();
+    if !missing.is_empty() {
+        return Err(format!("purge refused: consumers {:?} of linked nodes do not exist yet, nodes may need the whole queue", missing).into());
+    }
+
+    // потребители удаленных нод не учитываются, временные (replay, dump) учитываются всегда
+    let parts = list_parts()?;
+    let mut min_part = parts.last().map(|p| p.id).ok_or("queue is empty")?;
+    for name in list_consumer_names() {
+        // потребитель прерванного queue dump не удерживает части, он удаляется
+        if is_orphan_dump_consumer(&name) {
+            if dry_run {
+                println!("consumer {}\tleft by interrupted dump, will be deleted", name);
+            } else {
+                delete_consumer(&name).map_err(|e| format!("fail delete consumer {}: {:?}", name, e))?;
+                info!("consumer {} left by interrupted dump deleted", name);
+            }
+            continue;
+        }
+        if stale_consumers.contains(&name) {
+            warn!("consumer {} is stale: linked node uses another consumer in its exchange mode, ignored", name);
+            println!("consumer {}\tstale, not used in exchange mode of linked node, may be deleted (consumer delete)", name);
+            continue;
+        }
+        if (name.starts_with("i_") || name.starts_with("r_")) && !node_consumers.contains(&name) {
+            warn!("consumer {} does not belong to linked node, ignored", name);
+            continue;
+        }
+        let info = get_consumer_info(&name, &parts)?;
+        info!("consumer {}: part={}, pos={}, lag={}", info.name, info.part_id, info.count_popped, info.lag);
+        min_part = min_part.min(info.part_id);
+    }
+
+    let retention_limit = SystemTime::now() - Duration::from_secs(retention_days * 86400);
+    let mut count_purged = 0;
+    for part in parts.iter().filter(|p| p.id < min_part) {
+        let modified = get_part_modified(part.id)?;
+        if modified > retention_limit {
+            info!("part {} is kept until retention period ({} days) expires", part.id, retention_days);
+            break;
+        }
+
+        if dry_run {
+            println!("part {}\t{} messages\twill be purged", part.id, part.count_pushed);
+        } else {
+            remove_part(part.id, archive.as_deref()).map_err(|e| format!("fail purge part {}: {:?}", part.id, e))?;
+            info!("part {} ({} messages) purged{}", part.id, part.count_pushed, archive.as_ref().map(|a| format!(" to {}", a)).unwrap_or_default());
+        }
+        count_purged += 1;
+    }
+
+    println!("min consumer part={}, purged {} parts", min_part, count_purged);
+    Ok(())
+}
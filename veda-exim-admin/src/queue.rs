@@ -0,0 +1,180 @@
+/*
+ * Команды обслуживания очереди ./data/out/extract и ее потребителей
+ */
+use serde_json::json;
+use serde_json::Value;
+use std::error::Error;
+use std::path::Path;
+use v_common::onto::individual::{Individual, RawObj};
+use v_common::onto::parser::parse_raw;
+use v_exim::queue_tools::*;
+use v_queue::consumer::Consumer;
+
+// сколько сообщений выводит dump по умолчанию
+const DEFAULT_DUMP_COUNT: u32 = 10;
+
+pub fn get_arg(args: &[String], name: &str) -> Option<String> {
+    let idx = args.iter().position(|a| a == name)?;
+    args.get(idx + 1).cloned()
+}
+
+pub fn get_num_arg(args: &[String], name: &str) -> Result<Option<u32>, Box<dyn Error>> {
+    match get_arg(args, name) {
+        Some(v) => Ok(Some(v.parse::<u32>().map_err(|_| format!("{}: expected number, got [{}]", name, v))?)),
+        None => Ok(None),
+    }
+}
+
+// число дней от 1970-01-01 до даты по григорианскому календарю
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    // год начинается с марта, чтобы високосный день был последним
+    let (y, m) = if m <= 2 {
+        (y - 1, m + 9)
+    } else {
+        (y, m - 3)
+    };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// дата: секунды от 1970-01-01 либо YYYY-MM-DD[THH:MM:SS] (UTC)
+pub fn parse_date(value: &str) -> Result<i64, Box<dyn Error>> {
+    if let Ok(secs) = value.parse::<i64>() {
+        return Ok(secs);
+    }
+
+    let err = || format!("invalid date [{}], expected seconds or YYYY-MM-DD[THH:MM:SS]", value);
+    let (date, time) = value.split_once('T').unwrap_or((value, "00:00:00"));
+
+    let d: Vec<i64> = date.split('-').map(|v| v.parse::<i64>()).collect::<Result<_, _>>().map_err(|_| err())?;
+    let t: Vec<i64> = time.split(':').map(|v| v.parse::<i64>()).collect::<Result<_, _>>().map_err(|_| err())?;
+    if d.len() != 3 || t.len() != 3 || !(1..=12).contains(&d[1]) || !(1..=31).contains(&d[2]) || t[0] > 23 || t[1] > 59 || t[2] > 59 {
+        return Err(err().into());
+    }
+
+    Ok(days_from_civil(d[0], d[1], d[2]) * 86400 + t[0] * 3600 + t[1] * 60 + t[2])
+}
+
+// сообщение обмена либо элемент очереди с раскрытым состоянием индивида (new_state)
+pub fn message_json(indv: &mut Individual) -> (Value, Option<Value>) {
+    indv.parse_all();
+    let new_state = indv.get_first_binobj("new_state").and_then(|raw| {
+        let mut state = Individual::new_raw(RawObj::new(raw));
+        parse_raw(&mut state).ok()?;
+        state.parse_all();
+        Some(state.get_obj().as_json())
+    });
+    (indv.get_obj().as_json(), new_state)
+}
+
+pub fn queue_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(|s| s.as_str()) {
+        Some("parts") => {
+            for part in list_parts()? {
+                println!("{}\t{}", part.id, part.count_pushed);
+            }
+            Ok(())
+        },
+        Some("consumers") => {
+            let parts = list_parts()?;
+            for name in list_consumer_names() {
+                match get_consumer_info(&name, &parts) {
+                    Ok(c) => println!("{}\tpart={}\tpos={}\tlag={}", c.name, c.part_id, c.count_popped, c.lag),
+                    Err(e) => println!("{}\t{}", name, e),
+                }
+            }
+            Ok(())
+        },
+        Some("dump") => dump(&args[1..]),
+        _ => Err(super::usage().into()),
+    }
+}
+
+// временный потребитель dump: d_<pid процесса veda-exim-admin>
+fn get_dump_consumer_name() -> String {
+    format!("d_{}", std::process::id())
+}
+
+// потребитель dump, оставшийся после аварийного завершения veda-exim-admin: процесса с его pid нет
+pub fn is_orphan_dump_consumer(name: &str) -> bool {
+    match name.strip_prefix("d_").and_then(|pid| pid.parse::<u32>().ok()) {
+        Some(pid) => !Path::new(&format!("/proc/{}", pid)).exists(),
+        None => false,
+    }
+}
+
+// выводит сообщения очереди начиная с --part (по умолчанию первая часть) и --pos через временного потребителя
+fn dump(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let part_id = match get_num_arg(args, "--part")? {
+        Some(id) => id,
+        None => list_parts()?.first().map(|p| p.id).ok_or("queue is empty")?,
+    };
+    let position = get_num_arg(args, "--pos")?.unwrap_or(0);
+    let count = get_num_arg(args, "--count")?.unwrap_or(DEFAULT_DUMP_COUNT);
+
+    let consumer_name = get_dump_consumer_name();
+    let mut consumer = open_consumer(&consumer_name)?;
+    let res = dump_messages(&mut consumer, part_id, position, count);
+    delete_consumer(&consumer_name)?;
+    res
+}
+
+fn dump_messages(consumer: &mut Consumer, part_id: u32, position: u32, count: u32) -> Result<(), Box<dyn Error>> {
+    rewind_consumer(consumer, part_id, position)?;
+    for _ in 0..count {
+        let (part, pos) = (consumer.id, consumer.count_popped);
+        let raw = match peek_next(consumer) {
+            Some(raw) => raw,
+            None => break,
+        };
+
+        let mut queue_element = Individual::new_raw(RawObj::new(raw));
+        let out = if parse_raw(&mut queue_element).is_ok() {
+            let (content, new_state) = message_json(&mut queue_element);
+            json!({"part": part, "pos": pos, "content": content, "new_state": new_state})
+        } else {
+            json!({"part": part, "pos": pos, "error": "fail parse queue element"})
+        };
+        println!("{}", serde_json::to_string_pretty(&out)?);
+        consumer.commit();
+    }
+    Ok(())
+}
+
+pub fn consumer_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let cmd = args.first().map(|s| s.as_str());
+    let name = args.get(1).ok_or_else(super::usage)?;
+    if !is_consumer_exists(name) {
+        return Err(format!("not found consumer {}", name).into());
+    }
+
+    match cmd {
+        Some("rewind") => {
+            let mut consumer = open_consumer(name)?;
+            if let Some(date) = get_arg(args, "--date") {
+                let skipped = seek_consumer_to_date(&mut consumer, parse_date(&date)?)?;
+                info!("consumer {} moved to part={}, pos={}, skipped {} messages before {}", name, consumer.id, consumer.count_popped, skipped, date);
+            } else {
+                let part_id = get_num_arg(args, "--part")?.ok_or("rewind: expected --part <id> [--pos <n>] or --date <date>")?;
+                rewind_consumer(&mut consumer, part_id, get_num_arg(args, "--pos")?.unwrap_or(0))?;
+                info!("consumer {} moved to part={}, pos={}", name, consumer.id, consumer.count_popped);
+            }
+            Ok(())
+        },
+        Some("reset") => {
+            let mut consumer = open_consumer(name)?;
+            let skipped = reset_consumer(&mut consumer)?;
+            info!("consumer {} moved to end of queue, part={}, pos={}, skipped {} messages", name, consumer.id, consumer.count_popped, skipped);
+            Ok(())
+        },
+        Some("delete") => {
+            delete_consumer(name)?;
+            info!("consumer {} deleted", name);
+            Ok(())
+        },
+        _ => Err(super::usage().into()),
+    }
+}
@@ -0,0 +1,101 @@
+/*
+ * Повторная отправка ноде изменений начиная с даты (например, после восстановления
+ * ноды из резервной копии). Используется временный потребитель t_<node>_<дата>,
+ * обычный обмен с нодой при этом продолжается. Отправка останавливается на позиции
+ * потребителя обычного обмена на момент запуска: следующие изменения передает он,
+ * и состояние, замененное им до запуска, к ноде повторно не приходит. Прерванная отправка
+ * продолжается повторным запуском с теми же параметрами, по завершении потребитель удаляется
+ */
+use crate::queue::{get_arg, parse_date};
+use std::error::Error;
+use std::fs::{read_to_string, remove_file, write};
+use std::path::Path;
+use v_common::module::veda_backend::Backend;
+use v_common::storage::common::StorageMode;
+use v_exim::linked_node::LinkedNode;
+use v_exim::queue_tools::*;
+use v_exim::transport::create_transport;
+use v_exim::*;
+
+pub fn replay_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (node_id, from) = match (get_arg(args, "--node"), get_arg(args, "--from")) {
+        (Some(node_id), Some(from)) => (node_id, parse_date(&from)?),
+        _ => return Err("replay: expected --node <node_id> --from <date>".into()),
+    };
+
+    let mut backend = Backend::create(StorageMode::ReadOnly, false);
+    let remote_node = get_linked_nodes(&mut backend)
+        .iter_mut()
+        .filter_map(LinkedNode::new)
+        .find(|n| n.node_id == node_id)
+        .ok_or(format!("not found linked node {}", node_id))?;
+
+    let consumer_name = format!("t_{}_{}", node_id.replace(':', "_"), from);
+    let resume = is_consumer_exists(&consumer_name);
+
+    // позиция обычного обмена фиксируется при первом запуске и сохраняется для продолжения
+    let stop = match read_stop_position(&consumer_name) {
+        Some(stop) => stop,
+        None => {
+            let live_name = get_node_consumer_name(&remote_node);
+            if !is_consumer_exists(&live_name) {
+                return Err(format!("replay: consumer {} of node {} does not exist yet, the node will receive the whole queue", live_name, node_id).into());
+            }
+            let mut live = open_consumer(&live_name)?;
+            let mut stop = (live.id, live.count_popped);
+            // начало части - это конец предыдущей, иначе чтение перейдет в следующую часть и возьмет ее первое сообщение
+            if stop.1 == 0 && stop.0 > 0 && live.queue.get_info_of_part(stop.0 - 1, false).is_ok() {
+                stop = (stop.0 - 1, live.queue.count_pushed);
+            }
+            write(get_stop_path(&consumer_name), format!("{} {}", stop.0, stop.1))?;
+            stop
+        },
+    };
+
+    let mut consumer = open_consumer(&consumer_name)?;
+    if resume {
+        info!("replay to {}: continue from part={}, pos={}, stop at part={}, pos={}", node_id, consumer.id, consumer.count_popped, stop.0, stop.1);
+    } else {
+        let skipped = seek_consumer_to_date(&mut consumer, from)?;
+        info!("replay to {}: start from part={}, pos={}, stop at part={}, pos={}, skipped {} messages", node_id, consumer.id, consumer.count_popped, stop.0, stop.1, skipped);
+    }
+
+    let transport = create_transport(&remote_node.addr)?;
+    let mut total_sent = 0;
+    while (consumer.id, consumer.count_popped) < stop {
+        let pos = (consumer.id, consumer.count_popped);
+        let (count_sent, res) = send_changes_to_node_until(&mut consumer, transport.as_ref(), &remote_node.node_id, remote_node.relay, &remote_node.retry, stop);
+        total_sent += count_sent;
+
+        if res != ExImCode::Ok {
+            return Err(format!("replay to {} stopped at part={}, pos={}, err={:?}, sent {} messages, run again to continue", node_id, consumer.id, consumer.count_popped, res, total_sent).into());
+        }
+
+        // позиция обычного обмена всегда в пределах очереди, отсутствие продвижения означает, что дальше читать нечего
+        if (consumer.id, consumer.count_popped) == pos && !has_new_messages(&mut consumer) {
+            warn!("replay to {}: queue ends at part={}, pos={} before stop position", node_id, consumer.id, consumer.count_popped);
+            break;
+        }
+    }
+
+    delete_consumer(&consumer_name)?;
+    if Path::new(&get_stop_path(&consumer_name)).exists() {
+        remove_file(get_stop_path(&consumer_name))?;
+    }
+    info!("replay to {} completed, sent {} messages", node_id, total_sent);
+    Ok(())
+}
+
+// позиция остановки хранится в файле <base_path>/<consumer>_replay_stop: "<часть> <позиция>"
+fn get_stop_path(consumer_name: &str) -> String {
+    format!("{}/{}_replay_stop", QUEUE_BASE_PATH, consumer_name)
+}
+
+fn read_stop_position(consumer_name: &str) -> Option<(u32, u32)> {
+    let data = read_to_string(get_stop_path(consumer_name)).ok()?;
+    let mut it = data.split_whitespace().map(|v| v.parse::<u32>());
+    match (it.next(), it.next()) {
+        (Some(Ok(part)), Some(Ok(pos))) => Some((part, pos)),
+        _ => None,
+    }
+}
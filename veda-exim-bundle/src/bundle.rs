@@ -0,0 +1,77 @@
+/*
+ * Формат файла пакета изменений: gzip(JSON {"body": <строка>, "signature": <hex>}),
+ * body - сериализованная структура Bundle, signature - HMAC-SHA256 от body
+ * на ключе exim_bundle_key из veda.properties
+ */
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hmac::{Hmac, Mac};
+use serde_json::Value as JSONValue;
+use sha2::Sha256;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+use v_common::module::module_impl::Module;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Bundle {
+    // нода, сформировавшая пакет
+    pub source: String,
+    // нода, для которой сформирован пакет
+    pub target: String,
+    // порядковый номер пакета для пары (source, target)
+    pub seq: i64,
+    pub created: i64,
+    // сообщения в формате encode_message
+    pub messages: Vec<JSONValue>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedBundle {
+    body: String,
+    signature: String,
+}
+
+pub fn get_bundle_key() -> Result<String, Box<dyn Error>> {
+    match Module::get_property("exim_bundle_key") {
+        Some(key) if !key.is_empty() => Ok(key),
+        _ => Err("not found param exim_bundle_key in properties file".into()),
+    }
+}
+
+fn sign(key: &str, body: &str) -> Result<HmacSha256, Box<dyn Error>> {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes())?;
+    mac.update(body.as_bytes());
+    Ok(mac)
+}
+
+pub fn write_bundle(path: &str, bundle: &Bundle, key: &str) -> Result<(), Box<dyn Error>> {
+    let body = serde_json::to_string(bundle)?;
+    let signature = hex::encode(sign(key, &body)?.finalize().into_bytes());
+
+    let tmp_path = format!("{}.tmp", path);
+    let f = File::create(&tmp_path)?;
+    let mut encoder = GzEncoder::new(f, Compression::default());
+    encoder.write_all(serde_json::to_string(&SignedBundle {
+        body,
+        signature,
+    })?
+    .as_bytes())?;
+    encoder.finish()?.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+pub fn read_bundle(path: &str, key: &str) -> Result<Bundle, Box<dyn Error>> {
+    let mut data = String::new();
+    GzDecoder::new(File::open(path)?).read_to_string(&mut data)?;
+    let signed: SignedBundle = serde_json::from_str(&data)?;
+
+    // verify_slice сравнивает подписи за постоянное время
+    sign(key, &signed.body)?.verify_slice(&hex::decode(&signed.signature)?).map_err(|_| "invalid bundle signature")?;
+
+    Ok(serde_json::from_str(&signed.body)?)
+}
@@ -0,0 +1,208 @@
+/*
+ * Обмен с нодами без сетевой связи через файлы пакетов (переносимые носители).
+ *
+ * export-bundle --node <node_id> --out <file>
+ *      вычитывает изменения для ноды из очереди ./data/out/extract (отдельный потребитель b_<node>,
+ *      не мешающий сетевому обмену через i_<node>) и сохраняет их в подписанный сжатый файл пакета
+ * import-bundle <file> [--force]
+ *      проверяет подпись, принимает сообщения пакета и отмечает номер примененного пакета
+ *      в описании ноды-источника (cfg:bundle_seq_applied), повторно пакет не применяется.
+ *      Пакеты применяются по порядку номеров, --force - применить пакет, пропустив недостающие
+ */
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_derive;
+
+mod bundle;
+
+use crate::bundle::{get_bundle_key, read_bundle, write_bundle, Bundle};
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+use v_common::module::module_impl::init_log;
+use v_common::module::veda_backend::Backend;
+use v_common::onto::individual::{Individual, RawObj};
+use v_common::storage::common::StorageMode;
+use v_common::v_api::api_client::IndvOp;
+use v_common::v_api::obj::ResultCode;
+use v_exim::coalesce::PendingWindow;
+use v_exim::queue_tools::open_node_consumer;
+use v_exim::snapshot::is_snapshot_required;
+use v_exim::*;
+
+// сколько сообщений вычитывается из очереди за один проход
+const READ_BATCH: usize = 10000;
+
+fn main() {
+    init_log("EXIM_BUNDLE");
+
+    let args: Vec<String> = std::env::args().collect();
+    let mut backend = Backend::create(StorageMode::ReadOnly, false);
+
+    let res = match args.get(1).map(|s| s.as_str()) {
+        Some("export-bundle") => match (get_arg(&args, "--node"), get_arg(&args, "--out")) {
+            (Some(node_id), Some(out_path)) => export_bundle(&mut backend, &node_id, &out_path),
+            _ => Err("export-bundle: expected --node <node_id> --out <file>".into()),
+        },
+        Some("import-bundle") => match args.iter().skip(2).find(|a| !a.starts_with("--")) {
+            Some(path) => import_bundle(&mut backend, path, args.iter().any(|a| a == "--force")),
+            None => Err("import-bundle: expected <file> [--force]".into()),
+        },
+        _ => Err("usage: veda-exim-bundle export-bundle --node <node_id> --out <file> | import-bundle <file> [--force]".into()),
+    };
+
+    if let Err(e) = res {
+        error!("{}", e);
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn get_arg(args: &[String], name: &str) -> Option<String> {
+    let idx = args.iter().position(|a| a == name)?;
+    args.get(idx + 1).cloned()
+}
+
+fn prepare_ids(backend: &mut Backend) -> Result<(String, String), Box<dyn Error>> {
+    let sys_ticket = backend.get_sys_ticket_id().map_err(|_| "fail get system ticket")?;
+    let my_node_id = get_db_id(backend).ok_or("not found node id (cfg:system)")?;
+    info!("my node_id={}", my_node_id);
+    Ok((sys_ticket, my_node_id))
+}
+
+fn find_linked_node(backend: &mut Backend, node_id: &str) -> Option<Individual> {
+    get_linked_nodes(backend).into_iter().find_map(|mut link_node| {
+        if link_node.get_first_literal("cfg:node_id").as_deref() == Some(node_id) {
+            Some(link_node)
+        } else {
+            None
+        }
+    })
+}
+
+fn set_bundle_seq(backend: &mut Backend, sys_ticket: &str, link_node_id: &str, predicate: &str, seq: i64) -> Result<(), Box<dyn Error>> {
+    let mut indv = Individual::default();
+    indv.set_id(link_node_id);
+    indv.add_integer(predicate, seq);
+
+    let res = backend.mstorage_api.update(sys_ticket, IndvOp::SetIn, &indv);
+    if res.result != ResultCode::Ok {
+        return Err(format!("fail update {} of {}, result_code={:?}", predicate, link_node_id, res.result).into());
+    }
+    Ok(())
+}
+
+fn export_bundle(backend: &mut Backend, node_id: &str, out_path: &str) -> Result<(), Box<dyn Error>> {
+    let key = get_bundle_key()?;
+    let (sys_ticket, my_node_id) = prepare_ids(backend)?;
+
+    let mut link_node = find_linked_node(backend, node_id).ok_or(format!("not found linked node {}", node_id))?;
+    let relay = link_node.get_first_bool("cfg:relay").unwrap_or(false);
+
+    let consumer_name = format!("b_{}", node_id.replace(':', "_"));
+    let mut queue_consumer = open_node_consumer(&consumer_name, is_snapshot_required(&mut link_node))?;
+
+    // прочитанные сообщения сохраняются в окне ожидания до записи пакета,
+    // при сбое они войдут в следующий пакет
+    let mut pending = PendingWindow::new(&consumer_name);
+    loop {
+        let pos = (queue_consumer.id, queue_consumer.count_popped);
+        if fill_pending_window(&mut queue_consumer, &mut pending, READ_BATCH) != ExImCode::Ok {
+            return Err(format!("fail read queue, consumer={}", consumer_name).into());
+        }
+        if (queue_consumer.id, queue_consumer.count_popped) == pos {
+            break;
+        }
+    }
+
+    let mut messages = vec![];
+    for raw in pending.messages() {
+        match create_export_message(&mut Individual::new_raw(RawObj::new(raw.clone())), node_id, relay) {
+            Ok(mut out_obj) => messages.push(encode_message(&mut out_obj)?),
+            Err(e) => {
+                if e != ExImCode::Ok {
+                    error!("fail create out message {:?}", e);
+                }
+            },
+        }
+    }
+
+    if messages.is_empty() {
+        info!("no changes for node {}, bundle not created", node_id);
+        pending.store(vec![])?;
+        return Ok(());
+    }
+
+    // номер увеличивается до записи файла, чтобы два разных пакета не получили один номер
+    let seq = link_node.get_first_integer("cfg:bundle_seq_exported").unwrap_or(0) + 1;
+    set_bundle_seq(backend, &sys_ticket, link_node.get_id(), "cfg:bundle_seq_exported", seq)?;
+
+    let count = messages.len();
+    let bundle = Bundle {
+        source: my_node_id,
+        target: node_id.to_owned(),
+        seq,
+        created: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default(),
+        messages,
+    };
+    write_bundle(out_path, &bundle, &key)?;
+    pending.store(vec![])?;
+
+    info!("export bundle {}: node={}, seq={}, messages={}", out_path, node_id, seq, count);
+    Ok(())
+}
+
+// пакеты применяются строго по порядку номеров, force - применить пакет, несмотря на пропущенные
+fn import_bundle(backend: &mut Backend, path: &str, force: bool) -> Result<(), Box<dyn Error>> {
+    let key = get_bundle_key()?;
+    let (sys_ticket, my_node_id) = prepare_ids(backend)?;
+
+    let bundle = read_bundle(path, &key)?;
+    if bundle.target != my_node_id {
+        return Err(format!("bundle {} is intended for node {}", path, bundle.target).into());
+    }
+
+    let mut link_node = find_linked_node(backend, &bundle.source).ok_or(format!("bundle {}: unknown source node {}", path, bundle.source))?;
+
+    let applied = link_node.get_first_integer("cfg:bundle_seq_applied").unwrap_or(0);
+    if bundle.seq <= applied {
+        return Err(format!("bundle {} (seq={}) already applied, last applied seq={}", path, bundle.seq, applied).into());
+    }
+    // пропущенные пакеты после применения этого уже не будут приняты
+    if bundle.seq > applied + 1 {
+        if !force {
+            return Err(format!("bundle {} (seq={}): missing bundles from {} to {}, use --force to skip them", path, bundle.seq, applied + 1, bundle.seq - 1).into());
+        }
+        warn!("bundle {}: missing bundles from {} to {} are skipped (--force)", path, applied + 1, bundle.seq - 1);
+    }
+
+    let my_groups = get_node_group_membership(backend, &my_node_id);
+    let mut count_ok = 0;
+    let mut count_failed = 0;
+    for msg in bundle.messages.iter() {
+        let mut recv_indv = match decode_message(msg) {
+            Ok(indv) => indv,
+            Err(e) => {
+                error!("fail decode message of bundle {}, err={:?}", path, e);
+                count_failed += 1;
+                continue;
+            },
+        };
+        let res = processing_imported_message(&my_node_id, &my_groups, &mut recv_indv, &sys_ticket, &mut backend.mstorage_api);
+        if res.res_code != ExImCode::Ok {
+            error!("fail accept changes, uri={}, err={:?}, detail={}", res.id, res.res_code, res.detail.as_deref().unwrap_or_default());
+            count_failed += 1;
+        } else {
+            count_ok += 1;
+        }
+    }
+
+    // пакет с ошибками можно применить повторно, принятые сообщения перезапишутся тем же состоянием
+    if count_failed > 0 {
+        return Err(format!("bundle {}: {} messages failed, bundle not marked as applied", path, count_failed).into());
+    }
+
+    set_bundle_seq(backend, &sys_ticket, link_node.get_id(), "cfg:bundle_seq_applied", bundle.seq)?;
+    info!("import bundle {}: source={}, seq={}, messages={}", path, bundle.source, bundle.seq, count_ok);
+    Ok(())
+}
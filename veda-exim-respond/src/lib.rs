@@ -0,0 +1,712 @@
+/*
+ * Отвечает на запросы обмена: прием изменений (import_delta) и выдача
+ * накопленных изменений по запросу другой ноды (export_delta).
+ * Состояние обмена с нодами записывается в индивиды <связанная нода>_respond_status.
+ * Используется veda-exim-respond и veda-exim
+ */
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+use actix_web::App;
+use actix_web::{get, put, HttpResponse};
+use actix_web::{middleware, web, HttpServer};
+use futures::channel::oneshot;
+use futures::lock::Mutex;
+use futures::select;
+use futures::FutureExt;
+use nng::{Message, Protocol, Socket};
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::io::ErrorKind;
+use std::sync::{Arc, MutexGuard, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use v_common::module::module_impl::Module;
+use v_common::module::veda_backend::Backend;
+use v_common::storage::common::StorageMode;
+use v_common::onto::individual::Individual;
+use v_common::v_api::api_client::MStorageClient;
+use v_exim::coalesce::{get_coalesce_window, PendingWindow};
+use v_exim::dead_letter::DeadLetterQueue;
+use v_exim::error::ExImError;
+use v_exim::linked_node::{ExchangeMode, LinkedNode};
+use v_exim::message::get_sender;
+use v_exim::queue_tools::{open_node_consumer, open_queue};
+use v_exim::status::{get_queue_lag, store_status, NodeStatus, NodeStatuses, StatusModule};
+use v_exim::*;
+use v_exim::{decode_message, processing_imported_message};
+use v_queue::consumer::Consumer;
+
+// предельное время удержания запроса export_delta, меньше времени ожидания ответа у клиента
+const MAX_LONG_POLL_WAIT: u64 = 25000;
+// как часто проверяется пополнение очереди для ожидающих длинных запросов (мс)
+const QUEUE_CHECK_INTERVAL: u64 = 100;
+// как часто проверяются изменения описаний связанных нод (мс)
+const LINKED_NODES_CHECK_INTERVAL: u64 = 1000;
+const DEFAULT_EXPORT_BATCH_SIZE: usize = 100;
+const MAX_EXPORT_BATCH_SIZE: usize = 1000;
+
+#[derive(Deserialize)]
+struct ExportDeltaParams {
+    // сколько миллисекунд можно ждать появления изменений, если их нет
+    wait: Option<u64>,
+}
+
+#[get("/export_delta/{remote_node_id}")]
+async fn export_delta(
+    web::Path(remote_node_id): web::Path<String>,
+    params: web::Query<ExportDeltaParams>,
+    ctx: web::Data<Context>,
+) -> io::Result<HttpResponse> {
+    // this request changes from master
+    // читаем элемент очереди, создаем обьект и отправляем на server
+    let consumer = match open_export_consumer(&ctx, &remote_node_id) {
+        Some(c) => c,
+        None => return Ok(HttpResponse::Ok().json(json!({"msg": ""}))),
+    };
+
+    let coalesce_window = get_coalesce_window();
+
+    if !wait_export_data(&ctx, &consumer, &remote_node_id, params.wait.unwrap_or(0)).await {
+        return Ok(HttpResponse::Ok().json(json!({"msg": ""})));
+    }
+
+    let (msg, lag) = match lock_consumer(&consumer) {
+        Some(mut export_consumer) => (pop_export_delta(&ctx, &mut export_consumer, &remote_node_id, coalesce_window), get_queue_lag(&mut export_consumer.queue_consumer)),
+        None => return Ok(HttpResponse::Ok().json(json!({"msg": ""}))),
+    };
+
+    // выданное сообщение зафиксировано в очереди сразу, подтверждения не будет
+    if msg["msg"].as_str().map(|m| !m.is_empty()).unwrap_or(false) {
+        update_status(&ctx, &remote_node_id, |status| {
+            status.on_push(1);
+            status.set_queue_lag(lag);
+        });
+    }
+    Ok(HttpResponse::Ok().json(msg))
+}
+
+// потребитель очереди изменений для ноды, один для всех запросов ноды (HTTP и NNG)
+fn get_export_consumer(ctx: &Context, remote_node_id: &str) -> Option<SharedConsumer> {
+    let mut consumers = match ctx.consumers.lock() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("fail lock export consumers, err={:?}", e);
+            return None;
+        },
+    };
+    if let Some(consumer) = consumers.get(remote_node_id) {
+        return Some(consumer.clone());
+    }
+
+    let consumer_name = format!("r_{}", remote_node_id.replace(':', "_"));
+    match open_node_consumer(&consumer_name, ctx.is_snapshot_required(remote_node_id)) {
+        Ok(queue_consumer) => {
+            let consumer = Arc::new(std::sync::Mutex::new(ExportConsumer {
+                pending: PendingWindow::new(&consumer_name),
+                dlq: DeadLetterQueue::new(&consumer_name),
+                queue_consumer,
+            }));
+            consumers.insert(remote_node_id.to_owned(), consumer.clone());
+            Some(consumer)
+        },
+        Err(e) => {
+            error!("{}", e);
+            None
+        },
+    }
+}
+
+// потребитель очереди изменений для ноды, None - запрос изменений этой нодой не обслуживается
+fn open_export_consumer(ctx: &Context, remote_node_id: &str) -> Option<SharedConsumer> {
+    // peer нода сама получает изменения этой ноды (потребитель i_<node>), повторно их не передаем
+    if ctx.is_peer_node(remote_node_id) {
+        warn!("export_delta: {} is peer node, request ignored", remote_node_id);
+        return None;
+    }
+
+    // изменения для ноды в режиме push отправляются ей этой нодой из того же потребителя r_<node>
+    if ctx.is_push_node(remote_node_id) {
+        warn!("export_delta: changes are pushed to node {}, request ignored", remote_node_id);
+        return None;
+    }
+
+    get_export_consumer(ctx, remote_node_id)
+}
+
+// потребитель и его окно ожидания используются только под блокировкой, состояние части очереди перечитывается
+fn lock_consumer(consumer: &SharedConsumer) -> Option<MutexGuard<'_, ExportConsumer>> {
+    match consumer.lock() {
+        Ok(mut export_consumer) => {
+            let part_id = export_consumer.queue_consumer.id;
+            if let Err(e) = export_consumer.queue_consumer.queue.get_info_of_part(part_id, true) {
+                error!("get_info_of_part {}: {}", part_id, e.as_str());
+            }
+            Some(export_consumer)
+        },
+        Err(e) => {
+            error!("fail lock export consumer, err={:?}", e);
+            None
+        },
+    }
+}
+
+// длинный запрос: ждем появления изменений для ноды не дольше wait мс, false - изменений нет.
+// Потребитель проверяется вне потока обработки запросов (web::block) при запуске и после каждого пополнения очереди
+async fn wait_export_data(ctx: &Context, consumer: &SharedConsumer, remote_node_id: &str, wait: u64) -> bool {
+    let wait = wait.min(MAX_LONG_POLL_WAIT);
+    if wait == 0 {
+        return true;
+    }
+
+    let relay = ctx.is_relay_node(remote_node_id);
+    let deadline = Instant::now() + Duration::from_millis(wait);
+    loop {
+        // подписка до проверки, чтобы не пропустить пополнение очереди во время проверки
+        let changed = ctx.queue_watch.listen();
+
+        let check_consumer = consumer.clone();
+        let node_id = remote_node_id.to_owned();
+        let has_data = web::block(move || -> Result<bool, ()> {
+            Ok(lock_consumer(&check_consumer).map(|mut export_consumer| has_export_data(&mut export_consumer, &node_id, relay)).unwrap_or(false))
+        })
+        .await
+        .unwrap_or(false);
+        if has_data {
+            return true;
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        // уведомление не может быть получено (ошибка блокировки списка ожидающих) - ожидание заканчивается
+        if !matches!(async_std::future::timeout(deadline - now, changed).await, Ok(Ok(()))) {
+            return false;
+        }
+    }
+}
+
+// в окне ожидания остаются схлопываемые и еще не подтвержденные нодой сообщения,
+// элементы очереди для других нод при ожидании пропускаются
+fn has_export_data(export_consumer: &mut ExportConsumer, remote_node_id: &str, relay: bool) -> bool {
+    !export_consumer.pending.is_empty() || skip_foreign_messages(&mut export_consumer.queue_consumer, remote_node_id, relay)
+}
+
+// следующее сообщение для ноды, {"msg": ""} - изменений нет. Сообщение берется из того же окна ожидания,
+// что и порции export_batch; подтверждения у export_delta нет, поэтому выданное сообщение сразу удаляется из окна
+fn pop_export_delta(ctx: &Context, export_consumer: &mut ExportConsumer, remote_node_id: &str, coalesce_window: usize) -> Value {
+    let mut msg = match pull_export_batch(ctx, export_consumer, remote_node_id, 1, coalesce_window).pop() {
+        Some(msg) => msg,
+        None => return json!({"msg": ""}),
+    };
+    if let Some(pos) = msg["pos"].as_u64() {
+        if ack_pending_export_messages(&export_consumer.queue_consumer.name, &mut export_consumer.pending, &[pos]) != ExImCode::Ok {
+            return json!({"msg": ""});
+        }
+    }
+    if let Some(obj) = msg.as_object_mut() {
+        obj.remove("pos");
+    }
+    msg
+}
+
+#[derive(Deserialize)]
+struct ExportBatchParams {
+    max: Option<usize>,
+    wait: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ImportBatch {
+    msgs: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct AckParams {
+    // позиции подтверждаемых сообщений (поле pos сообщений порции)
+    positions: Vec<u64>,
+}
+
+#[get("/hello")]
+async fn hello(ctx: web::Data<Context>) -> io::Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(json!({"node_id": ctx.node_id})))
+}
+
+// порция изменений для ноды, выданные сообщения хранятся до подтверждения (ack)
+#[get("/export_batch/{remote_node_id}")]
+async fn export_batch(
+    web::Path(remote_node_id): web::Path<String>,
+    params: web::Query<ExportBatchParams>,
+    ctx: web::Data<Context>,
+) -> io::Result<HttpResponse> {
+    let consumer = match open_export_consumer(&ctx, &remote_node_id) {
+        Some(c) => c,
+        None => return Ok(HttpResponse::Ok().json(json!({"msgs": []}))),
+    };
+
+    let coalesce_window = get_coalesce_window();
+
+    if !wait_export_data(&ctx, &consumer, &remote_node_id, params.wait.unwrap_or(0)).await {
+        return Ok(HttpResponse::Ok().json(json!({"msgs": []})));
+    }
+
+    let max_count = params.max.unwrap_or(DEFAULT_EXPORT_BATCH_SIZE).clamp(1, MAX_EXPORT_BATCH_SIZE);
+    let (msgs, lag) = match lock_consumer(&consumer) {
+        Some(mut export_consumer) => (pull_export_batch(&ctx, &mut export_consumer, &remote_node_id, max_count, coalesce_window), get_queue_lag(&mut export_consumer.queue_consumer)),
+        None => return Ok(HttpResponse::Ok().json(json!({"msgs": []}))),
+    };
+
+    update_status(&ctx, &remote_node_id, |status| status.set_queue_lag(lag));
+
+    Ok(HttpResponse::Ok().json(json!({ "msgs": msgs })))
+}
+
+#[put("/ack/{remote_node_id}")]
+async fn ack(web::Path(remote_node_id): web::Path<String>, params: web::Json<AckParams>, ctx: web::Data<Context>) -> io::Result<HttpResponse> {
+    let res = ack_export_batch(&ctx, &remote_node_id, &params.positions);
+    record_ack(&ctx, &remote_node_id, params.positions.len(), &res);
+
+    if res != ExImCode::Ok {
+        return Ok(HttpResponse::InternalServerError().json(json!({ "res_code": res })));
+    }
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+#[put("/import_batch")]
+async fn import_batch(batch: web::Json<ImportBatch>, mstorage: web::Data<Mutex<MStorageClient>>, ctx: web::Data<Context>) -> io::Result<HttpResponse> {
+    let mut ms = mstorage.lock().await;
+    Ok(HttpResponse::Ok().json(import_messages(&ctx, &mut ms, &batch.msgs)))
+}
+
+fn pull_export_batch(ctx: &Context, export_consumer: &mut ExportConsumer, remote_node_id: &str, max_count: usize, coalesce_window: usize) -> Vec<Value> {
+    let relay = ctx.is_relay_node(remote_node_id);
+    let ExportConsumer {
+        queue_consumer,
+        pending,
+        dlq,
+    } = export_consumer;
+    get_pending_export_messages(queue_consumer, pending, dlq, remote_node_id, relay, max_count, coalesce_window)
+}
+
+fn ack_export_batch(ctx: &Context, remote_node_id: &str, positions: &[u64]) -> ExImCode {
+    match get_export_consumer(ctx, remote_node_id).as_ref().and_then(lock_consumer) {
+        Some(mut export_consumer) => {
+            let ExportConsumer {
+                queue_consumer,
+                pending,
+                ..
+            } = &mut *export_consumer;
+            ack_pending_export_messages(&queue_consumer.name, pending, positions)
+        },
+        None => ExImCode::FailUpdate,
+    }
+}
+
+fn import_messages(ctx: &Context, mstorage: &mut MStorageClient, msgs: &[Value]) -> Vec<IOResult> {
+    let mut results = vec![];
+    // состояние обмена ведется по ноде, передавшей сообщение: число принятых и последняя ошибка
+    let mut by_sender: HashMap<String, (i32, Option<ExImCode>)> = HashMap::new();
+
+    for msg in msgs {
+        let res = if let Ok(mut recv_indv) = decode_message(msg) {
+            let sender = get_sender(&mut recv_indv);
+            let res = processing_imported_message(&ctx.node_id, &ctx.get_node_groups(), &mut recv_indv, &ctx.sys_ticket, mstorage);
+
+            let counters = by_sender.entry(sender).or_default();
+            if res.res_code == ExImCode::Ok {
+                counters.0 += 1;
+            } else {
+                counters.1 = Some(res.res_code.clone());
+            }
+            res
+        } else {
+            IOResult::from_error("", &ExImError::InvalidField("msg"))
+        };
+        results.push(res);
+    }
+
+    for (sender, (count_recv, error)) in by_sender {
+        update_status(ctx, &sender, |status| {
+            status.on_pull(count_recv);
+            if let Some(e) = error {
+                status.on_error(e);
+            }
+        });
+    }
+    results
+}
+
+// изменяет состояние обмена со связанной нодой в памяти, записывает его поток наблюдения за связанными нодами
+fn update_status(ctx: &Context, remote_node_id: &str, update: impl FnOnce(&mut NodeStatus)) {
+    if let Ok(mut statuses) = ctx.statuses.lock() {
+        if let Some(status) = statuses.get_mut(remote_node_id) {
+            update(status);
+        }
+    }
+}
+
+fn record_ack(ctx: &Context, remote_node_id: &str, count: usize, res: &ExImCode) {
+    update_status(ctx, remote_node_id, |status| {
+        if *res == ExImCode::Ok {
+            status.on_push(count as i32);
+        } else {
+            status.on_error(res.clone());
+        }
+    });
+}
+
+#[put("/import_delta")]
+async fn import_delta(msg: web::Json<Value>, mstorage: web::Data<Mutex<MStorageClient>>, ctx: web::Data<Context>) -> io::Result<HttpResponse> {
+    if decode_message(&msg.0).is_ok() {
+        let mut ms = mstorage.lock().await;
+        let res = import_messages(&ctx, &mut ms, std::slice::from_ref(&msg.0)).pop();
+        return Ok(HttpResponse::Ok().json(res));
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+// потребитель r_<node> с окном ожидания и очередью недоставленных сообщений; окно хранится в памяти,
+// файл окна перезаписывается только при его изменении
+pub struct ExportConsumer {
+    pub queue_consumer: Consumer,
+    pub pending: PendingWindow,
+    pub dlq: DeadLetterQueue,
+}
+
+// потребитель r_<node> и его окно ожидания используются одновременно HTTP и NNG запросами ноды
+pub type SharedConsumer = Arc<std::sync::Mutex<ExportConsumer>>;
+pub type ExportConsumers = Arc<std::sync::Mutex<HashMap<String, SharedConsumer>>>;
+
+// уведомляет длинные запросы о пополнении очереди. Очередь пополняет veda-extractor (другой процесс),
+// поэтому ее состояние проверяет один поток (watch_queue), а не каждый ожидающий запрос
+#[derive(Clone, Default)]
+pub struct QueueWatch {
+    listeners: Arc<std::sync::Mutex<Vec<oneshot::Sender<()>>>>,
+}
+
+impl QueueWatch {
+    fn listen(&self) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        if let Ok(mut listeners) = self.listeners.lock() {
+            // запросы, ожидание которых закончилось
+            listeners.retain(|l| !l.is_canceled());
+            listeners.push(tx);
+        }
+        rx
+    }
+
+    fn notify(&self) {
+        let listeners = match self.listeners.lock() {
+            Ok(mut listeners) => std::mem::take(&mut *listeners),
+            Err(_) => return,
+        };
+        for tx in listeners {
+            let _ = tx.send(());
+        }
+    }
+}
+
+fn watch_queue(watch: &QueueWatch) {
+    let mut last_state = None;
+    loop {
+        match open_queue() {
+            Ok(mut queue) => loop {
+                queue.get_info_queue();
+                let part_id = queue.id;
+                if let Err(e) = queue.get_info_of_part(part_id, true) {
+                    error!("get_info_of_part {}: {}", part_id, e.as_str());
+                    break;
+                }
+                let state = Some((part_id, queue.count_pushed));
+                if state != last_state {
+                    last_state = state;
+                    watch.notify();
+                }
+                thread::sleep(Duration::from_millis(QUEUE_CHECK_INTERVAL));
+            },
+            Err(e) => error!("{}", e),
+        }
+        thread::sleep(Duration::from_millis(LINKED_NODES_CHECK_INTERVAL));
+    }
+}
+
+// режимы связанных нод, перечитываются при изменении описаний нод (load_linked_nodes)
+#[derive(Default)]
+pub struct LinkedNodeModes {
+    // ноды с симметричным обменом (cfg:exim_mode "peer")
+    pub peer_nodes: Vec<String>,
+    // ноды, которым эта нода сама отправляет изменения (cfg:exim_mode "push")
+    pub push_nodes: Vec<String>,
+    // ноды, для которых запрошена полная выгрузка (cfg:snapshot_status)
+    pub snapshot_nodes: Vec<String>,
+    // транзитные ноды (cfg:relay), им передаются сообщения и для нод, с которыми нет прямой связи
+    pub relay_nodes: Vec<String>,
+    // группы нод, в которые входит эта нода
+    pub node_groups: Vec<String>,
+}
+
+impl LinkedNodeModes {
+    fn load(backend: &mut Backend, my_node_id: &str, linked_nodes: &HashMap<String, LinkedNode>) -> Self {
+        let peer_nodes: Vec<String> = linked_nodes.values().filter(|n| n.mode == ExchangeMode::Peer).map(|n| n.node_id.clone()).collect();
+        info!("peer nodes: {:?}", peer_nodes);
+
+        let push_nodes: Vec<String> = linked_nodes.values().filter(|n| n.mode == ExchangeMode::Push).map(|n| n.node_id.clone()).collect();
+        info!("push nodes: {:?}", push_nodes);
+
+        let snapshot_nodes: Vec<String> = linked_nodes.values().filter(|n| n.snapshot_required).map(|n| n.node_id.clone()).collect();
+
+        let relay_nodes: Vec<String> = linked_nodes.values().filter(|n| n.relay).map(|n| n.node_id.clone()).collect();
+        info!("relay nodes: {:?}", relay_nodes);
+
+        LinkedNodeModes {
+            peer_nodes,
+            push_nodes,
+            snapshot_nodes,
+            relay_nodes,
+            node_groups: get_node_group_membership(backend, my_node_id),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Context {
+    pub node_id: String,
+    pub sys_ticket: String,
+    pub modes: Arc<RwLock<LinkedNodeModes>>,
+    // потребители очереди для нод, запрашивающих изменения
+    pub consumers: ExportConsumers,
+    pub queue_watch: QueueWatch,
+    // состояние обмена со связанными нодами
+    pub statuses: NodeStatuses,
+}
+
+impl Context {
+    pub fn is_peer_node(&self, node_id: &str) -> bool {
+        self.modes.read().map(|m| m.peer_nodes.iter().any(|n| n == node_id)).unwrap_or(false)
+    }
+
+    pub fn is_push_node(&self, node_id: &str) -> bool {
+        self.modes.read().map(|m| m.push_nodes.iter().any(|n| n == node_id)).unwrap_or(false)
+    }
+
+    pub fn is_relay_node(&self, node_id: &str) -> bool {
+        self.modes.read().map(|m| m.relay_nodes.iter().any(|n| n == node_id)).unwrap_or(false)
+    }
+
+    pub fn is_snapshot_required(&self, node_id: &str) -> bool {
+        self.modes.read().map(|m| m.snapshot_nodes.iter().any(|n| n == node_id)).unwrap_or(false)
+    }
+
+    pub fn get_node_groups(&self) -> Vec<String> {
+        self.modes.read().map(|m| m.node_groups.clone()).unwrap_or_default()
+    }
+}
+
+pub fn get_respond_port() -> io::Result<u16> {
+    let param_name = "exim_respond_port";
+    let exim_respond_port = Module::get_property(param_name);
+    if exim_respond_port.is_none() {
+        return Err(std::io::Error::new(ErrorKind::NotFound, format!("not found param {} in properties file", param_name)));
+    }
+    Ok(exim_respond_port.unwrap().parse::<u16>().unwrap_or(5588))
+}
+
+pub fn prepare_context(backend: &mut Backend) -> io::Result<Context> {
+    let sys_ticket;
+    if let Ok(t) = backend.get_sys_ticket_id() {
+        sys_ticket = t;
+    } else {
+        return Err(std::io::Error::new(ErrorKind::NotFound, "fail get system ticket"));
+    }
+
+    let mut node_id = get_db_id(backend);
+    if node_id.is_none() {
+        node_id = create_db_id(backend);
+    }
+
+    if node_id.is_none() {
+        return Err(std::io::Error::new(ErrorKind::NotFound, "fail create node_id"));
+    }
+    let node_id = node_id.unwrap();
+    info!("my node_id={}", node_id);
+
+    let mut node_upd_counter = 0;
+    let mut linked_nodes = HashMap::new();
+    load_linked_nodes(backend, &mut node_upd_counter, &mut linked_nodes);
+
+    let statuses: HashMap<String, NodeStatus> = linked_nodes.values().map(|n| (n.node_id.clone(), NodeStatus::load(backend, StatusModule::Respond, &n.id, &n.node_id))).collect();
+
+    let ctx = Context {
+        modes: Arc::new(RwLock::new(LinkedNodeModes::load(backend, &node_id, &linked_nodes))),
+        node_id,
+        sys_ticket,
+        consumers: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        queue_watch: QueueWatch::default(),
+        statuses: Arc::new(std::sync::Mutex::new(statuses)),
+    };
+
+    let queue_watch = ctx.queue_watch.clone();
+    thread::spawn(move || watch_queue(&queue_watch));
+
+    // изменения режимов связанных нод учитываются без перезапуска, там же записывается состояние обмена
+    let watch_ctx = ctx.clone();
+    thread::spawn(move || {
+        let mut backend = Backend::create(StorageMode::ReadOnly, false);
+        watch_linked_nodes(&mut backend, &watch_ctx, node_upd_counter, linked_nodes);
+    });
+
+    Ok(ctx)
+}
+
+fn watch_linked_nodes(backend: &mut Backend, ctx: &Context, mut node_upd_counter: i64, mut linked_nodes: HashMap<String, LinkedNode>) {
+    loop {
+        thread::sleep(Duration::from_millis(LINKED_NODES_CHECK_INTERVAL));
+
+        let prev_counter = node_upd_counter;
+        load_linked_nodes(backend, &mut node_upd_counter, &mut linked_nodes);
+        if node_upd_counter != prev_counter {
+            let modes = LinkedNodeModes::load(backend, &ctx.node_id, &linked_nodes);
+            if let Ok(mut m) = ctx.modes.write() {
+                *m = modes;
+            }
+            add_new_statuses(backend, ctx, &linked_nodes);
+        }
+
+        store_statuses(backend, ctx);
+    }
+}
+
+// состояния для нод, связанных после запуска
+fn add_new_statuses(backend: &mut Backend, ctx: &Context, linked_nodes: &HashMap<String, LinkedNode>) {
+    let known: Vec<String> = match ctx.statuses.lock() {
+        Ok(statuses) => statuses.keys().cloned().collect(),
+        Err(_) => return,
+    };
+    let new_statuses: Vec<(String, NodeStatus)> = linked_nodes
+        .values()
+        .filter(|n| !known.contains(&n.node_id))
+        .map(|n| (n.node_id.clone(), NodeStatus::load(backend, StatusModule::Respond, &n.id, &n.node_id)))
+        .collect();
+    if new_statuses.is_empty() {
+        return;
+    }
+    if let Ok(mut statuses) = ctx.statuses.lock() {
+        for (node_id, status) in new_statuses {
+            statuses.entry(node_id).or_insert(status);
+        }
+    }
+}
+
+// изменения берутся под блокировкой, запись выполняется после ее снятия
+fn store_statuses(backend: &mut Backend, ctx: &Context) {
+    let changed: Vec<Individual> = match ctx.statuses.lock() {
+        Ok(mut statuses) => statuses.values_mut().filter_map(|status| status.take_if_needed()).collect(),
+        Err(_) => return,
+    };
+    for indv in changed.iter() {
+        store_status(&mut backend.mstorage_api, &ctx.sys_ticket, indv);
+    }
+}
+
+pub fn get_respond_nng_url() -> Option<String> {
+    Module::get_property("exim_respond_nng_url").filter(|url| !url.is_empty())
+}
+
+// прием запросов по протоколу nanomsg/NNG в режиме req-rep, формат запросов описан в v_exim::transport.
+// Запросы обслуживаются последовательно, поэтому изменения выдаются без ожидания (параметр wait не учитывается)
+pub fn run_nng_server(url: &str, ctx: Context) -> Result<(), nng::Error> {
+    let server = Socket::new(Protocol::Rep0)?;
+    server.listen(url)?;
+    info!("listen nng {}", url);
+
+    let mut mstorage = MStorageClient::new(Module::get_property("main_module_url").unwrap_or_default());
+    loop {
+        let req = server.recv()?;
+
+        let res = match serde_json::from_slice::<Value>(&req) {
+            Ok(req) => process_nng_request(&ctx, &mut mstorage, &req),
+            Err(e) => {
+                error!("nng: fail parse request, err={:?}", e);
+                json!({"msg": ""})
+            },
+        };
+
+        if let Err((_, e)) = server.send(Message::from(res.to_string().as_bytes())) {
+            error!("nng: fail send response, err={:?}", e);
+        }
+    }
+}
+
+fn process_nng_request(ctx: &Context, mstorage: &mut MStorageClient, req: &Value) -> Value {
+    match req["cmd"].as_str() {
+        Some("hello") => json!({"node_id": ctx.node_id}),
+        Some("import_batch") => {
+            let msgs = req["msgs"].as_array().cloned().unwrap_or_default();
+            serde_json::to_value(import_messages(ctx, mstorage, &msgs)).unwrap_or_default()
+        },
+        Some("export_batch") => {
+            let remote_node_id = req["node_id"].as_str().unwrap_or_default();
+            let max_count = req["max"].as_u64().map(|m| m as usize).unwrap_or(DEFAULT_EXPORT_BATCH_SIZE).clamp(1, MAX_EXPORT_BATCH_SIZE);
+            let msgs = if let Some(mut export_consumer) = open_export_consumer(ctx, remote_node_id).as_ref().and_then(lock_consumer) {
+                let msgs = pull_export_batch(ctx, &mut export_consumer, remote_node_id, max_count, get_coalesce_window());
+                let lag = get_queue_lag(&mut export_consumer.queue_consumer);
+                drop(export_consumer);
+                update_status(ctx, remote_node_id, |status| status.set_queue_lag(lag));
+                msgs
+            } else {
+                vec![]
+            };
+            json!({ "msgs": msgs })
+        },
+        Some("ack") => {
+            let remote_node_id = req["node_id"].as_str().unwrap_or_default();
+            let positions: Vec<u64> = req["positions"].as_array().map(|a| a.iter().filter_map(|p| p.as_u64()).collect()).unwrap_or_default();
+            let res = ack_export_batch(ctx, remote_node_id, &positions);
+            record_ack(ctx, remote_node_id, positions.len(), &res);
+            json!({ "res_code": res })
+        },
+        _ => {
+            error!("nng: unknown request {}", req["cmd"]);
+            json!({})
+        },
+    }
+}
+
+pub async fn run_server(port: u16, ctx: Context) -> io::Result<()> {
+    let mut server_future = HttpServer::new(move || {
+        let json_cfg = web::JsonConfig::default().limit(5 * 1024 * 1024);
+        App::new()
+            .app_data(json_cfg)
+            .wrap(middleware::Compress::default())
+            .wrap(
+                middleware::DefaultHeaders::new()
+                    .header("Server", "nginx/1.19.6")
+                    .header("X-XSS-Protection", "1; mode=block")
+                    .header("X-Content-Type-Options", "nosniff")
+                    .header("X-Frame-Options", "sameorigin")
+                    .header("Cache-Control", "no-cache, no-store, must-revalidate, private"),
+            )
+            .data(ctx.clone())
+            .data(Mutex::new(MStorageClient::new(Module::get_property("main_module_url").unwrap_or_default())))
+            .service(hello)
+            .service(export_delta)
+            .service(import_delta)
+            .service(export_batch)
+            .service(import_batch)
+            .service(ack)
+    })
+    .bind(format!("0.0.0.0:{}", port))?
+    .run()
+    .fuse();
+
+    select! {
+        _r = server_future => println!("Server is stopped!"),
+    };
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+/*
+ * Симметричный (peer) режим обмена: один сервис одновременно отвечает на
+ * запросы других нод (как veda-exim-respond) и сам проводит сеансы обмена
+ * со связанными нодами (как veda-exim-inquire).
+ *
+ * Свои изменения нода отправляет только через потребителя i_<node>, для нод
+ * в режиме peer запрос export_delta (потребитель r_<node>) не обслуживается,
+ * поэтому каждое изменение передается один раз
+ */
+#[macro_use]
+extern crate log;
+
+use std::thread;
+use v_common::module::module_impl::init_log_with_params;
+use v_common::module::veda_backend::Backend;
+use v_common::storage::common::StorageMode;
+use v_exim::inquire::inquire_linked_nodes;
+use v_exim::linked_node::ExchangeMode;
+use veda_exim_respond::{get_respond_nng_url, get_respond_port, prepare_context, run_nng_server, run_server};
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "actix_server=info,actix_web=info");
+    init_log_with_params("EXIM", None, true);
+    info!("START EXIM");
+    let mut backend = Backend::default();
+
+    let exim_respond_port = get_respond_port()?;
+    let ctx = prepare_context(&mut backend)?;
+
+    let my_node_id = ctx.node_id.clone();
+    let sys_ticket = ctx.sys_ticket.clone();
+    thread::spawn(move || {
+        let mut backend = Backend::create(StorageMode::ReadOnly, false);
+        inquire_linked_nodes(&mut backend, &my_node_id, &sys_ticket, &[ExchangeMode::Inquire, ExchangeMode::Peer, ExchangeMode::Push]);
+    });
+
+    if let Some(nng_url) = get_respond_nng_url() {
+        let nng_ctx = ctx.clone();
+        thread::spawn(move || {
+            if let Err(e) = run_nng_server(&nng_url, nng_ctx) {
+                error!("nng server {} is stopped, err={:?}", nng_url, e);
+            }
+        });
+    }
+
+    run_server(exim_respond_port, ctx).await
+}
@@ -3,14 +3,29 @@
  * следует ли выгружать в другую систему. Проверка производится методом
  * is_exportable, если ответ успешный, то происходит запись в очередь
  * ./data/out/extract
+ *
+ * Для новых связанных нод с cfg:snapshot_status = "required" выполняет
+ * полную выгрузку (snapshot) адресованную только этой ноде
+ *
+ * Сообщения, принятые этой нодой как транзитной (./data/out/relay), переносит
+ * в очередь ./data/out/extract без изменений
  */
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate lazy_static;
 
-use crate::v8_script::{is_exportable, load_exim_filter_scripts};
+use crate::projection::{load_export_policies, Projection};
+use crate::sent_references::SentReferences;
+use crate::v8_script::{is_exportable, load_exim_filter_scripts, FilterOptions};
+use std::collections::{HashMap, HashSet};
 use std::{env, fs, thread, time};
+use v_exim::delta::{get_delta, is_empty_delta};
+use v_exim::linked_node::LinkedNode;
+use v_exim::message::{ExImMessage, VISITED_PREDICATE};
+use v_exim::queue_tools::{get_node_consumer_name, open_node_consumer};
+use v_exim::relay::{list_relay_messages, read_relay_message, remove_relay_message};
+use v_exim::snapshot::*;
 use v_exim::*;
 use v_queue::consumer::*;
 use v_queue::queue::*;
@@ -24,7 +39,6 @@ use v_v8::v_common::module::info::ModuleInfo;
 use v_v8::v_common::module::module_impl::{get_cmd, get_inner_binobj_as_individual, init_log, Module, PrepareError};
 use v_v8::v_common::module::remote_indv_r_storage::inproc_storage_manager;
 use v_v8::v_common::module::veda_backend::Backend;
-use v_v8::v_common::onto::datatype::Lang;
 use v_v8::v_common::onto::individual::Individual;
 use v_v8::v_common::onto::individual2msgpack::to_msgpack;
 use v_v8::v_common::onto::onto_impl::Onto;
@@ -33,8 +47,13 @@ use v_v8::v_common::storage::common::StorageMode;
 use v_v8::v_common::v_api::api_client::IndvOp;
 use v_v8::v_common::v_api::obj::ResultCode;
 
+mod projection;
+mod sent_references;
 mod v8_script;
 
+// пользователь, от имени которого выполняются скрипты
+const SYSTEM_USER: &str = "cfg:VedaSystem";
+
 pub struct Context<'a> {
     sys_ticket: String,
     db_id: String,
@@ -42,6 +61,29 @@ pub struct Context<'a> {
     workplace: ScriptsWorkPlace<'a, ScriptInfoContext>,
     xr: XapianReader,
     onto: Onto,
+    filters: HashMap<String, FilterOptions>,
+    // группа нод (v-s:LinkedNodeGroup) -> node_id участников
+    node_groups: HashMap<String, Vec<String>>,
+    // node_id -> правила выгрузки (v-s:EximPolicy)
+    export_policies: HashMap<String, Vec<Projection>>,
+    // linked node id -> v-s:updateCounter, для которого snapshot уже выполнен
+    snapshot_counters: HashMap<String, i64>,
+    // linked node id -> cfg:snapshot_date выполненной выгрузки (сохраняется между перезапусками)
+    completed_snapshots: HashMap<String, i64>,
+    // индивиды, уже выгруженные нодам по ссылкам
+    sent_references: SentReferences,
+    // выполняемая полная выгрузка
+    active_snapshot: Option<ActiveSnapshot>,
+}
+
+struct ActiveSnapshot {
+    // id v-s:LinkedNode
+    link_node_id: String,
+    node_id: String,
+    // cfg:snapshot_date статуса in_progress этой выгрузки
+    started: i64,
+    queries: Vec<String>,
+    pos: SnapshotPosition,
 }
 
 fn main() -> Result<(), i32> {
@@ -102,17 +144,26 @@ fn listen_queue<'a>(js_runtime: &'a mut JsRuntime) -> Result<(), i32> {
             workplace: ScriptsWorkPlace::new(js_runtime.v8_isolate()),
             xr,
             onto,
+            filters: HashMap::new(),
+            node_groups: HashMap::new(),
+            export_policies: HashMap::new(),
+            snapshot_counters: HashMap::new(),
+            completed_snapshots: load_completed_snapshots(),
+            sent_references: SentReferences::default(),
+            active_snapshot: None,
         };
 
         ctx.workplace.load_ext_scripts(&ctx.sys_ticket);
-        load_exim_filter_scripts(&mut ctx.workplace, &mut ctx.xr);
+        load_exim_filter_scripts(&mut ctx.workplace, &mut ctx.xr, &mut ctx.filters);
+        ctx.export_policies = load_export_policies(&mut backend, &mut ctx.xr);
+        ctx.node_groups = get_node_groups(&mut backend);
 
         let args: Vec<String> = env::args().collect();
         for el in args.iter() {
             if el.starts_with("--query") {
                 if let Some(i) = el.find('=') {
                     let query = el.to_string().split_off(i + 1).replace('\'', "'");
-                    if let Err(e) = export_from_query(&query, &mut backend, &mut ctx) {
+                    if let Err(e) = export_from_query(&query, &mut backend, &mut ctx, None) {
                         error!("fail execute query [{}], err={:?}", query, e);
                     }
                 }
@@ -132,15 +183,17 @@ fn listen_queue<'a>(js_runtime: &'a mut JsRuntime) -> Result<(), i32> {
     Ok(())
 }
 
-fn heartbeat(_backend: &mut Backend, _ctx: &mut Context) -> Result<(), PrepareError> {
-    Ok(())
+fn heartbeat(backend: &mut Backend, ctx: &mut Context) -> Result<(), PrepareError> {
+    move_relay_messages(ctx)?;
+    export_snapshots(backend, ctx)
 }
 
 fn before_batch(_backend: &mut Backend, _ctx: &mut Context, _size_batch: u32) -> Option<u32> {
     None
 }
 
-fn after_batch(_backend: &mut Backend, _ctx: &mut Context, _prepared_batch_size: u32) -> Result<bool, PrepareError> {
+fn after_batch(_backend: &mut Backend, ctx: &mut Context, _prepared_batch_size: u32) -> Result<bool, PrepareError> {
+    move_relay_messages(ctx)?;
     Ok(false)
 }
 
@@ -169,7 +222,29 @@ fn prepare(backend: &mut Backend, ctx: &mut Context, queue_element: &mut Individ
     //    if date.is_none() {
     //        return Ok(());
     //    }
-    prepare_indv(backend, ctx, &id, cmd.unwrap(), Some(&mut prev_state), &mut new_state, &user_id, date.unwrap_or_default(), queue_element.get_id())
+    prepare_indv(backend, ctx, &id, cmd.unwrap(), Some(&mut prev_state), &mut new_state, &user_id, date.unwrap_or_default(), queue_element.get_id(), None)
+}
+
+// сообщения, принятые этой нодой как транзитной, переносятся в очередь выгрузки в порядке поступления
+fn move_relay_messages(ctx: &mut Context) -> Result<(), PrepareError> {
+    for path in list_relay_messages() {
+        let raw = match read_relay_message(&path) {
+            Ok(raw) => raw,
+            Err(e) => {
+                error!("fail read relay message {:?}, err={:?}", path, e);
+                return Err(PrepareError::Recoverable);
+            },
+        };
+        if let Err(e) = ctx.queue_out.push(&raw, MsgType::Object) {
+            error!("fail push relay message into queue, err={:?}", e);
+            return Err(PrepareError::Fatal);
+        }
+        if let Err(e) = remove_relay_message(&path) {
+            error!("fail remove relay message {:?}, err={:?}", path, e);
+            return Err(PrepareError::Fatal);
+        }
+    }
+    Ok(())
 }
 
 fn prepare_indv(
@@ -177,36 +252,102 @@ fn prepare_indv(
     ctx: &mut Context,
     id: &str,
     cmd: IndvOp,
-    prev_state: Option<&mut Individual>,
+    mut prev_state: Option<&mut Individual>,
     new_state: &mut Individual,
     user_id: &str,
     date: i64,
     msg_id: &str,
+    only_target: Option<&str>,
 ) -> Result<bool, PrepareError> {
-    let mut export_list = is_exportable(backend, ctx, prev_state, new_state, user_id);
+    // маршрут принятого обменом изменения не выгружается как данные индивида
+    let route = take_visited(new_state);
+    if let Some(prev) = prev_state.as_deref_mut() {
+        take_visited(prev);
+    }
+
+    let mut export_list = is_exportable(backend, ctx, prev_state.as_deref_mut(), new_state, user_id);
     if export_list.is_empty() {
         return Ok(true);
     }
 
+    let mut delta: Option<(Individual, Individual)> = None;
+
+    // принятые обменом изменения (event_id "exim") сюда не попадают. Изменение, сделанное системой (скриптом)
+    // в индивиде, записанном обменом, продолжает его маршрут и не возвращается нодам, через которые прошло;
+    // изменение пользователя выгружается как новое
+    let mut visited = vec![ctx.db_id.clone()];
+    if only_target.is_none() && user_id == SYSTEM_USER {
+        for node_id in route {
+            if !visited.contains(&node_id) {
+                visited.push(node_id);
+            }
+        }
+    }
+
     for el in export_list.iter_mut() {
-        if let Some(indv) = &mut el.indv {
-            if indv.any_exists("rdf:type", &["v-s:File"]) {
-                let src_full_path = "data/files".to_owned()
-                    + &indv.get_first_literal("v-s:filePath").unwrap_or_default()
-                    + "/"
-                    + &indv.get_first_literal("v-s:fileUri").unwrap_or_default();
-
-                if let Ok(f) = fs::read(src_full_path) {
-                    indv.add_binary("v-s:fileData", f);
-                }
+        el.targets = resolve_targets(ctx, &el.targets);
+        el.targets.retain(|t| !visited.contains(t));
+
+        // при snapshot выгрузке результат адресуется только одной ноде
+        if let Some(node_id) = only_target {
+            if !el.targets.iter().any(|t| t == "*" || t == node_id) {
+                continue;
             }
-            let res = add_to_queue(id, &mut ctx.queue_out, cmd.clone(), indv, msg_id, &ctx.db_id, &el.target, date, el.enable_scripts);
+            el.targets = vec![node_id.to_owned()];
+        }
+
+        if el.targets.is_empty() {
+            continue;
+        }
+
+        // индивиды по ссылкам ставятся в очередь раньше ссылающегося на них индивида
+        if cmd != IndvOp::Remove {
+            let exported = if let Some(indv) = &mut el.indv {
+                indv
+            } else {
+                &mut *new_state
+            };
+            export_references(backend, ctx, exported, &el.filter_id, &el.targets, date, msg_id, el.enable_scripts, &visited)?;
+        }
+
+        if let Some(indv) = &mut el.indv {
+            let mut projected = project(ctx, &el.filter_id, &el.targets, indv);
+            let indv = projected.as_mut().unwrap_or(indv);
+            attach_file_data(indv);
+            let res = add_to_queue(id, &mut ctx.queue_out, cmd.clone(), indv, msg_id, &ctx.db_id, &el.targets, date, el.enable_scripts, &visited);
             if let Err(e) = res {
                 error!("fail prepare message, err={:?}", e);
                 return Err(PrepareError::Fatal);
             }
+        } else if is_delta_export(ctx, &el.filter_id, &cmd, &prev_state) {
+            if delta.is_none() {
+                if let Some(prev) = prev_state.as_deref_mut() {
+                    delta = Some(get_delta(prev, new_state));
+                }
+            }
+
+            if let Some((added, removed)) = &mut delta {
+                // сначала удаляем прежние значения, затем добавляем новые
+                for (patch_cmd, patch) in [(IndvOp::RemoveFrom, removed), (IndvOp::AddTo, added)] {
+                    if is_empty_delta(patch) {
+                        continue;
+                    }
+                    let mut projected = project(ctx, &el.filter_id, &el.targets, patch);
+                    let patch = projected.as_mut().unwrap_or(patch);
+                    if is_empty_delta(patch) {
+                        continue;
+                    }
+                    let res = add_to_queue(id, &mut ctx.queue_out, patch_cmd, patch, msg_id, &ctx.db_id, &el.targets, date, el.enable_scripts, &visited);
+                    if let Err(e) = res {
+                        error!("fail prepare message, err={:?}", e);
+                        return Err(PrepareError::Fatal);
+                    }
+                }
+            }
         } else {
-            let res = add_to_queue(id, &mut ctx.queue_out, cmd.clone(), new_state, msg_id, &ctx.db_id, &el.target, date, el.enable_scripts);
+            let mut projected = project(ctx, &el.filter_id, &el.targets, new_state);
+            let out_state = projected.as_mut().unwrap_or(new_state);
+            let res = add_to_queue(id, &mut ctx.queue_out, cmd.clone(), out_state, msg_id, &ctx.db_id, &el.targets, date, el.enable_scripts, &visited);
             if let Err(e) = res {
                 error!("fail prepare message, err={:?}", e);
                 return Err(PrepareError::Fatal);
@@ -217,6 +358,170 @@ fn prepare_indv(
     Ok(true)
 }
 
+fn take_visited(indv: &mut Individual) -> Vec<String> {
+    indv.parse_all();
+    let res = indv.get_literals(VISITED_PREDICATE).unwrap_or_default();
+    indv.remove(VISITED_PREDICATE);
+    res
+}
+
+// заменяет группы нод на их участников
+fn resolve_targets(ctx: &Context, targets: &[String]) -> Vec<String> {
+    let mut res: Vec<String> = vec![];
+    for target in targets {
+        let members = if let Some(members) = ctx.node_groups.get(target) {
+            members.clone()
+        } else {
+            vec![target.to_owned()]
+        };
+        for node_id in members {
+            if !res.contains(&node_id) {
+                res.push(node_id);
+            }
+        }
+    }
+    res
+}
+
+// применяет правила выгрузки фильтра и ноды к копии индивида, None - правил нет;
+// сообщение для всех нод ("*") ограничивается правилами всех нод, для нескольких нод - правилами каждой из них
+fn project(ctx: &Context, filter_id: &str, targets: &[String], indv: &mut Individual) -> Option<Individual> {
+    let mut projections = vec![];
+    if let Some(f) = ctx.filters.get(filter_id) {
+        if !f.projection.is_empty() {
+            projections.push(&f.projection);
+        }
+    }
+    for (node_id, policies) in ctx.export_policies.iter() {
+        if targets.iter().any(|t| t == "*" || t == node_id) {
+            projections.extend(policies.iter());
+        }
+    }
+
+    if projections.is_empty() {
+        return None;
+    }
+
+    indv.parse_all();
+    let mut out_indv = Individual::new_from_obj(indv.get_obj());
+    for p in projections {
+        p.apply(&mut out_indv);
+    }
+    Some(out_indv)
+}
+
+fn attach_file_data(indv: &mut Individual) {
+    if indv.any_exists("rdf:type", &["v-s:File"]) {
+        let src_full_path = "data/files".to_owned()
+            + &indv.get_first_literal("v-s:filePath").unwrap_or_default()
+            + "/"
+            + &indv.get_first_literal("v-s:fileUri").unwrap_or_default();
+
+        if let Ok(f) = fs::read(src_full_path) {
+            indv.add_binary("v-s:fileData", f);
+        }
+    }
+}
+
+// выгрузка индивидов, на которые ссылается выгружаемый индивид по предикатам
+// v-s:exportFollowPredicate фильтра, до глубины v-s:exportFollowDepth. Более глубокие уровни
+// ставятся в очередь раньше, чтобы у получателя не появлялись ссылки на еще не принятые индивиды.
+// Индивид, который нода уже получила с тем же v-s:updateCounter, повторно не выгружается
+fn export_references(
+    backend: &mut Backend,
+    ctx: &mut Context,
+    indv: &mut Individual,
+    filter_id: &str,
+    targets: &[String],
+    date: i64,
+    msg_id: &str,
+    enable_scripts: bool,
+    visited: &[String],
+) -> Result<(), PrepareError> {
+    let (predicates, max_depth) = match ctx.filters.get(filter_id) {
+        Some(f) if !f.follow_predicates.is_empty() && f.follow_depth > 0 => (f.follow_predicates.clone(), f.follow_depth),
+        _ => return Ok(()),
+    };
+
+    let mut seen = HashSet::new();
+    seen.insert(indv.get_id().to_owned());
+
+    let mut refs = vec![];
+    let mut level = get_references(indv, &predicates);
+    for _ in 0..max_depth {
+        let mut next_level = vec![];
+
+        for ref_id in level {
+            if !seen.insert(ref_id.clone()) {
+                continue;
+            }
+
+            let mut ref_indv = Individual::default();
+            if !backend.storage.get_individual(&ref_id, &mut ref_indv) {
+                warn!("export references: not found {}, referenced from {}", ref_id, indv.get_id());
+                continue;
+            }
+            take_visited(&mut ref_indv);
+            next_level.append(&mut get_references(&mut ref_indv, &predicates));
+            refs.push((ref_id, ref_indv));
+        }
+        level = next_level;
+    }
+
+    for (ref_id, mut ref_indv) in refs.into_iter().rev() {
+        // без счетчика нельзя определить, изменился ли индивид
+        let upd_counter = ref_indv.get_first_integer("v-s:updateCounter");
+        let ref_targets = if let Some(counter) = upd_counter {
+            ctx.sent_references.filter_targets(&ref_id, counter, targets)
+        } else {
+            targets.to_vec()
+        };
+        if ref_targets.is_empty() {
+            continue;
+        }
+
+        if let Some(projected) = project(ctx, filter_id, &ref_targets, &mut ref_indv) {
+            ref_indv = projected;
+        }
+        attach_file_data(&mut ref_indv);
+        let res = add_to_queue(&ref_id, &mut ctx.queue_out, IndvOp::Put, &mut ref_indv, msg_id, &ctx.db_id, &ref_targets, date, enable_scripts, visited);
+        if let Err(e) = res {
+            error!("fail prepare message, err={:?}", e);
+            return Err(PrepareError::Fatal);
+        }
+
+        // запись после постановки в очередь: при сбое между ними индивид будет выгружен повторно, но не потерян
+        if let Some(counter) = upd_counter {
+            if let Err(e) = ctx.sent_references.mark_sent(&ref_id, counter, &ref_targets) {
+                error!("fail store sent reference {}, err={:?}", ref_id, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_references(indv: &mut Individual, predicates: &[String]) -> Vec<String> {
+    let mut res = vec![];
+    for predicate in predicates {
+        res.append(&mut indv.get_literals(predicate).unwrap_or_default());
+    }
+    res
+}
+
+// патчи имеют смысл только для изменения существующего индивида
+fn is_delta_export(ctx: &Context, filter_id: &str, cmd: &IndvOp, prev_state: &Option<&mut Individual>) -> bool {
+    if *cmd != IndvOp::Put {
+        return false;
+    }
+    if !ctx.filters.get(filter_id).map(|f| f.export_delta).unwrap_or(false) {
+        return false;
+    }
+    if let Some(prev) = prev_state {
+        return !prev.is_empty();
+    }
+    false
+}
+
 fn add_to_queue(
     id: &str,
     queue_out: &mut Queue,
@@ -224,30 +529,40 @@ fn add_to_queue(
     new_state_indv: &mut Individual,
     msg_id: &str,
     source: &str,
-    target: &str,
+    targets: &[String],
     date: i64,
     enable_scripts: bool,
+    visited: &[String],
 ) -> Result<(), i32> {
     new_state_indv.parse_all();
 
     let mut raw: Vec<u8> = Vec::new();
     if to_msgpack(new_state_indv, &mut raw).is_ok() {
-        let mut new_indv = Individual::default();
-        new_indv.set_id(msg_id);
-        new_indv.add_uri("uri", id);
-        if !new_state_indv.is_empty() {
-            new_indv.add_binary("new_state", raw);
+        let msg = ExImMessage {
+            id: msg_id.to_owned(),
+            uri: id.to_owned(),
+            cmd,
+            new_state: if new_state_indv.is_empty() {
+                None
+            } else {
+                Some(raw)
+            },
+            date,
+            source_veda: source.to_owned(),
+            target_veda: targets.to_vec(),
+            enable_scripts,
+            visited: visited.to_vec(),
+        };
+
+        if let Err(e) = msg.validate() {
+            error!("invalid export message, uri={}: {}", id, e);
+            return Err(-3);
         }
-        new_indv.add_integer("cmd", cmd.to_i64());
-        new_indv.add_integer("date", date);
-        new_indv.add_string("source_veda", source, Lang::none());
-        new_indv.add_string("target_veda", target, Lang::none());
-        new_indv.add_bool("enable_scripts", enable_scripts);
 
-        info!("export: cmd={}, uri={}, src={}, target={}, enable_scripts={}", cmd.as_string(), id, &source, &target, enable_scripts);
+        info!("export: cmd={}, uri={}, src={}, target={:?}, enable_scripts={}", msg.cmd.as_string(), id, &source, targets, enable_scripts);
 
         let mut raw1: Vec<u8> = Vec::new();
-        if let Err(e) = to_msgpack(&new_indv, &mut raw1) {
+        if let Err(e) = to_msgpack(&msg.to_individual(), &mut raw1) {
             error!("fail serialize, err={:?}", e);
             return Err(-2);
         }
@@ -259,19 +574,193 @@ fn add_to_queue(
     Ok(())
 }
 
-fn export_from_query(query: &str, backend: &mut Backend, ctx: &mut Context) -> Result<(), PrepareError> {
+// сколько индивидов запрашивается за один запрос при выгрузке по запросу
+const QUERY_PAGE_SIZE: i32 = 10000;
+
+fn export_from_query(query: &str, backend: &mut Backend, ctx: &mut Context, only_target: Option<&str>) -> Result<(), PrepareError> {
+    let mut from = 0;
+    while let Some(next) = export_query_page(query, from, backend, ctx, only_target)? {
+        from = next;
+    }
+    Ok(())
+}
+
+// выгружает одну порцию результатов запроса, возвращает начало следующей порции, None - выборка прочитана полностью
+fn export_query_page(query: &str, from: i32, backend: &mut Backend, ctx: &mut Context, only_target: Option<&str>) -> Result<Option<i32>, PrepareError> {
     let mut ftq = FTQuery::new_with_user("cfg:VedaSystem", query);
-    ftq.top = 100000;
-    ftq.limit = 100000;
+    ftq.top = QUERY_PAGE_SIZE;
+    ftq.limit = QUERY_PAGE_SIZE;
+    ftq.from = from;
     info!("execute query [{:?}]", ftq);
     let res = ctx.xr.query(ftq, &mut backend.storage);
-    if res.result_code == ResultCode::Ok && res.count > 0 {
-        for id in &res.result {
-            if let Some(indv) = backend.get_individual(id, &mut Individual::default()) {
-                let msg_id = indv.get_id().to_string();
-                prepare_indv(backend, ctx, &indv.get_id().to_string(), IndvOp::Put, None, indv, "", 0, &msg_id)?;
-            }
+    if res.result_code != ResultCode::Ok {
+        error!("fail execute query [{}], from={}, result_code={:?}", query, from, res.result_code);
+        return Err(PrepareError::Recoverable);
+    }
+
+    for id in &res.result {
+        if let Some(indv) = backend.get_individual(id, &mut Individual::default()) {
+            let msg_id = indv.get_id().to_string();
+            prepare_indv(backend, ctx, &indv.get_id().to_string(), IndvOp::Put, None, indv, "", 0, &msg_id, only_target)?;
+        }
+    }
+
+    if res.cursor >= res.estimated || res.cursor <= from as i64 {
+        return Ok(None);
+    }
+    Ok(Some(res.cursor as i32))
+}
+
+// полная выгрузка выполняется порциями: за один вызов выгружается одна порция результатов запроса,
+// чтобы выгрузка не останавливала обработку очереди изменений
+fn export_snapshots(backend: &mut Backend, ctx: &mut Context) -> Result<(), PrepareError> {
+    if ctx.active_snapshot.is_none() {
+        ctx.active_snapshot = start_snapshot(backend, ctx);
+    }
+    let mut snapshot = match ctx.active_snapshot.take() {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+
+    if let Some(query) = snapshot.queries.get(snapshot.pos.query_index).cloned() {
+        match export_query_page(&query, snapshot.pos.from, backend, ctx, Some(&snapshot.node_id)) {
+            Ok(Some(next)) => {
+                snapshot.pos.from = next;
+            },
+            Ok(None) => {
+                snapshot.pos.query_index += 1;
+                snapshot.pos.from = 0;
+            },
+            Err(e) => {
+                error!("snapshot: fail execute query [{}] for node {}", query, snapshot.node_id);
+                if set_snapshot_status(backend, &ctx.sys_ticket, &snapshot.link_node_id, SnapshotStatus::Failed).is_none() {
+                    error!("snapshot: fail set status failed of {}", snapshot.link_node_id);
+                }
+                return Err(e);
+            },
         }
     }
+
+    if snapshot.pos.query_index < snapshot.queries.len() {
+        // при сбое записи после перезапуска будет повторно выгружена порция с ранее записанной позиции
+        set_snapshot_position(backend, &ctx.sys_ticket, &snapshot.link_node_id, &snapshot.pos);
+        ctx.active_snapshot = Some(snapshot);
+        return Ok(());
+    }
+
+    info!("snapshot: end full export for node {}", snapshot.node_id);
+    ctx.completed_snapshots.insert(snapshot.link_node_id.clone(), snapshot.started);
+    if let Err(e) = store_completed_snapshots(&ctx.completed_snapshots) {
+        error!("snapshot: fail store completed snapshots, err={:?}", e);
+    }
+    // статус будет записан повторно при следующей проверке
+    if set_snapshot_status(backend, &ctx.sys_ticket, &snapshot.link_node_id, SnapshotStatus::Done).is_none() {
+        ctx.snapshot_counters.remove(&snapshot.link_node_id);
+    }
     Ok(())
 }
+
+// выбирает ноду, для которой запрошена полная выгрузка; выгрузка, прерванная перезапуском, продолжается с записанной позиции
+fn start_snapshot(backend: &mut Backend, ctx: &mut Context) -> Option<ActiveSnapshot> {
+    for mut link_node in get_nodes_required_snapshot(backend) {
+        let node_id = link_node.get_first_literal("cfg:node_id").unwrap_or_default();
+        if node_id.is_empty() {
+            error!("snapshot: linked node {} has no cfg:node_id", link_node.get_id());
+            continue;
+        }
+        let link_node_id = link_node.get_id().to_owned();
+
+        // статус обновляется через mstorage асинхронно, не повторяем уже выполненную выгрузку
+        let upd_counter = link_node.get_first_integer("v-s:updateCounter").unwrap_or_default();
+        if ctx.snapshot_counters.get(&link_node_id) == Some(&upd_counter) {
+            continue;
+        }
+
+        let status = get_snapshot_status(&mut link_node);
+        let snapshot_date = get_snapshot_date(&mut link_node);
+        let queries = if let Some(q) = link_node.get_first_literal("cfg:snapshot_query") {
+            vec![q]
+        } else {
+            get_snapshot_queries(ctx)
+        };
+
+        if status == SnapshotStatus::InProgress {
+            if let Some(started) = snapshot_date {
+                // выгрузка выполнена, но статус done не был записан
+                if ctx.completed_snapshots.get(&link_node_id).copied() == snapshot_date {
+                    if set_snapshot_status(backend, &ctx.sys_ticket, &link_node_id, SnapshotStatus::Done).is_some() {
+                        ctx.snapshot_counters.insert(link_node_id, upd_counter);
+                    }
+                    continue;
+                }
+
+                let pos = get_snapshot_position(&mut link_node);
+                info!("snapshot: continue full export for node {}, query {}, from {}", node_id, pos.query_index, pos.from);
+                ctx.snapshot_counters.insert(link_node_id.clone(), upd_counter);
+                return Some(ActiveSnapshot {
+                    link_node_id,
+                    node_id,
+                    started,
+                    queries,
+                    pos,
+                });
+            }
+        }
+
+        info!("snapshot: start full export for node {}", node_id);
+
+        // новый потребитель ноды устанавливается в конец очереди до выгрузки первой порции,
+        // накопленные ранее изменения нода получит в составе выгрузки
+        if let Some(node) = LinkedNode::new(&mut link_node) {
+            if let Err(e) = open_node_consumer(&get_node_consumer_name(&node), true) {
+                error!("snapshot: {}", e);
+            }
+        }
+
+        // позиция прежней выгрузки не должна использоваться после перезапуска
+        let pos = SnapshotPosition::default();
+        if !set_snapshot_position(backend, &ctx.sys_ticket, &link_node_id, &pos) {
+            error!("snapshot: fail reset position of {}, export postponed", link_node_id);
+            continue;
+        }
+        let started = match set_snapshot_status(backend, &ctx.sys_ticket, &link_node_id, SnapshotStatus::InProgress) {
+            Some(date) => date,
+            None => {
+                error!("snapshot: fail set status of {}, export postponed", link_node_id);
+                continue;
+            },
+        };
+        ctx.snapshot_counters.insert(link_node_id.clone(), upd_counter);
+
+        // полная выгрузка передает ноде индивиды заново, в том числе те, на которые есть ссылки
+        if let Err(e) = ctx.sent_references.clear(&node_id) {
+            error!("snapshot: fail clear sent references of {}, err={:?}", node_id, e);
+        }
+
+        return Some(ActiveSnapshot {
+            link_node_id,
+            node_id,
+            started,
+            queries,
+            pos,
+        });
+    }
+    None
+}
+
+// полный набор данных определяется типами на которые реагируют фильтры выгрузки
+fn get_snapshot_queries(ctx: &Context) -> Vec<String> {
+    let mut types = vec![];
+    for script_id in ctx.workplace.scripts_order.iter() {
+        if let Some(script) = ctx.workplace.scripts.get(script_id) {
+            if script.compiled_script.is_some() {
+                for t in script.context.trigger_by_type.vec.iter() {
+                    if !types.contains(t) {
+                        types.push(t.to_owned());
+                    }
+                }
+            }
+        }
+    }
+    types.iter().map(|t| format!("'rdf:type' === '{}'", t)).collect()
+}
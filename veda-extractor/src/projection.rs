@@ -0,0 +1,135 @@
+/*
+ * Ограничение состава выгружаемых предикатов и маскирование значений.
+ * Правила задаются в фильтре v-s:EximFilter либо в индивиде v-s:EximPolicy
+ * для конкретной ноды (cfg:node_id)
+ */
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use v_v8::v_common::ft_xapian::xapian_reader::XapianReader;
+use v_v8::v_common::module::veda_backend::Backend;
+use v_v8::v_common::onto::datatype::Lang;
+use v_v8::v_common::onto::individual::Individual;
+use v_v8::v_common::onto::resource::Value;
+use v_v8::v_common::search::common::FTQuery;
+use v_v8::v_common::v_api::obj::ResultCode;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaskRule {
+    Drop,
+    Hash,
+    Replace(String),
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Projection {
+    // если список не пуст, выгружаются только эти предикаты
+    allowed: Vec<String>,
+    denied: Vec<String>,
+    masks: HashMap<String, MaskRule>,
+}
+
+impl Projection {
+    pub fn new(indv: &mut Individual) -> Self {
+        let mut masks = HashMap::new();
+
+        // формат правила: "<предикат> drop", "<предикат> hash", "<предикат> replace <значение>"
+        for rule in indv.get_literals("v-s:exportMask").unwrap_or_default() {
+            let mut parts = rule.trim().splitn(3, ' ');
+            let predicate = parts.next().unwrap_or_default();
+            let mask = match parts.next().unwrap_or_default() {
+                "drop" => MaskRule::Drop,
+                "hash" => MaskRule::Hash,
+                "replace" => MaskRule::Replace(parts.next().unwrap_or_default().to_owned()),
+                _ => {
+                    error!("{}: invalid mask rule [{}]", indv.get_id(), rule);
+                    continue;
+                },
+            };
+            masks.insert(predicate.to_owned(), mask);
+        }
+
+        Projection {
+            allowed: indv.get_literals("v-s:exportAllowedPredicate").unwrap_or_default(),
+            denied: indv.get_literals("v-s:exportDeniedPredicate").unwrap_or_default(),
+            masks,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allowed.is_empty() && self.denied.is_empty() && self.masks.is_empty()
+    }
+
+    fn is_allowed(&self, predicate: &str) -> bool {
+        // тип нужен принимающей стороне для обработки индивида
+        if predicate == "rdf:type" {
+            return true;
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|p| p == predicate) {
+            return false;
+        }
+        !self.denied.iter().any(|p| p == predicate)
+    }
+
+    pub fn apply(&self, indv: &mut Individual) {
+        indv.parse_all();
+
+        for predicate in indv.get_predicates() {
+            if !self.is_allowed(&predicate) {
+                indv.remove(&predicate);
+                continue;
+            }
+
+            match self.masks.get(&predicate) {
+                Some(MaskRule::Drop) => {
+                    indv.remove(&predicate);
+                },
+                Some(MaskRule::Hash) => {
+                    let values = indv.get_resources(&predicate).unwrap_or_default();
+                    indv.remove(&predicate);
+                    for r in values {
+                        indv.add_string(&predicate, &hash_value(&r.value), Lang::none());
+                    }
+                },
+                Some(MaskRule::Replace(value)) => {
+                    indv.remove(&predicate);
+                    indv.add_string(&predicate, value, Lang::none());
+                },
+                None => {},
+            }
+        }
+    }
+}
+
+// хэш значения любого типа, маскированное значение выгружается строкой
+fn hash_value(value: &Value) -> String {
+    let digest = match value {
+        Value::Str(s, _) | Value::Uri(s) => Sha256::digest(s.as_bytes()),
+        Value::Binary(data) => Sha256::digest(data),
+        Value::Int(v) | Value::Datetime(v) => Sha256::digest(v.to_string().as_bytes()),
+        Value::Num(mantissa, exponent) => Sha256::digest(format!("{}e{}", mantissa, exponent).as_bytes()),
+        Value::Bool(v) => Sha256::digest(v.to_string().as_bytes()),
+    };
+    format!("{:x}", digest)
+}
+
+// правила выгрузки для отдельных нод: node_id -> правила
+pub(crate) fn load_export_policies(backend: &mut Backend, xr: &mut XapianReader) -> HashMap<String, Vec<Projection>> {
+    let mut policies = HashMap::new();
+    let res = xr.query(FTQuery::new_with_user("cfg:VedaSystem", "'rdf:type' === 'v-s:EximPolicy'"), &mut backend.storage);
+
+    if res.result_code == ResultCode::Ok && res.count > 0 {
+        for id in &res.result {
+            if let Some(policy_indv) = backend.get_individual(id, &mut Individual::default()) {
+                if policy_indv.is_exists_bool("v-s:deleted", true) || policy_indv.is_exists_bool("v-s:disabled", true) {
+                    continue;
+                }
+                let projection = Projection::new(policy_indv);
+                for node_id in policy_indv.get_literals("cfg:node_id").unwrap_or_default() {
+                    policies.entry(node_id).or_default().push(projection.clone());
+                }
+            }
+        }
+    }
+    info!("load export policies for nodes: {:?}", policies.keys());
+    policies
+}
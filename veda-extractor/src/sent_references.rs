@@ -0,0 +1,95 @@
+/*
+ * Индивиды, выгруженные по ссылкам (v-s:exportFollowPredicate), для каждой ноды-получателя:
+ * uri -> v-s:updateCounter поставленного в очередь состояния. Хранится в ./data/out/sent_refs/<нода>
+ * строками "<updateCounter> <uri>", новое состояние индивида дописывается в конец файла. При загрузке
+ * файл сжимается, если строк в нем больше чем вдвое против числа индивидов
+ */
+use std::collections::HashMap;
+use std::fs::{create_dir_all, remove_file, rename, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+
+const SENT_REFS_PATH: &str = "./data/out/sent_refs";
+
+// файлы меньшего размера не сжимаются
+const MIN_COMPACT_LINES: usize = 1000;
+
+fn get_path(target: &str) -> String {
+    // сообщения для всех нод ("*") учитываются отдельно от сообщений конкретной ноде
+    let name = if target == "*" {
+        "all".to_owned()
+    } else {
+        target.replace(':', "_")
+    };
+    format!("{}/{}", SENT_REFS_PATH, name)
+}
+
+fn load(target: &str) -> HashMap<String, i64> {
+    let path = get_path(target);
+    let mut res = HashMap::new();
+    let mut count_lines = 0;
+    if let Ok(f) = File::open(&path) {
+        for line in BufReader::new(f).lines().map_while(Result::ok) {
+            if let Some((counter, uri)) = line.split_once(' ') {
+                if let Ok(counter) = counter.parse::<i64>() {
+                    res.insert(uri.to_owned(), counter);
+                    count_lines += 1;
+                }
+            }
+        }
+    }
+
+    if count_lines > MIN_COMPACT_LINES && count_lines > res.len() * 2 {
+        if let Err(e) = store(&path, &res) {
+            error!("fail compact sent references {}, err={:?}", path, e);
+        }
+    }
+    res
+}
+
+fn store(path: &str, sent: &HashMap<String, i64>) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut f = File::create(&tmp_path)?;
+    for (uri, counter) in sent.iter() {
+        writeln!(f, "{} {}", counter, uri)?;
+    }
+    f.sync_all()?;
+    rename(&tmp_path, path)
+}
+
+#[derive(Default)]
+pub struct SentReferences {
+    // нода -> (uri -> v-s:updateCounter)
+    targets: HashMap<String, HashMap<String, i64>>,
+}
+
+impl SentReferences {
+    fn get_target(&mut self, target: &str) -> &mut HashMap<String, i64> {
+        self.targets.entry(target.to_owned()).or_insert_with(|| load(target))
+    }
+
+    // ноды из targets, которые еще не получили индивид uri в состоянии counter
+    pub fn filter_targets(&mut self, uri: &str, counter: i64, targets: &[String]) -> Vec<String> {
+        targets.iter().filter(|t| self.get_target(t).get(uri) != Some(&counter)).cloned().collect()
+    }
+
+    pub fn mark_sent(&mut self, uri: &str, counter: i64, targets: &[String]) -> io::Result<()> {
+        create_dir_all(SENT_REFS_PATH)?;
+        for target in targets {
+            let mut f = OpenOptions::new().create(true).append(true).open(get_path(target))?;
+            writeln!(f, "{} {}", counter, uri)?;
+            self.get_target(target).insert(uri.to_owned(), counter);
+        }
+        Ok(())
+    }
+
+    // нода получит все индивиды заново (полная выгрузка)
+    pub fn clear(&mut self, target: &str) -> io::Result<()> {
+        self.targets.remove(target);
+        if let Err(e) = remove_file(get_path(target)) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
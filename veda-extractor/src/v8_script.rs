@@ -1,5 +1,6 @@
+use crate::projection::Projection;
 use crate::Context;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Mutex;
 use v_v8::callback::*;
 use v_v8::common::*;
@@ -28,11 +29,44 @@ impl Drop for SetupGuard {
 
 #[derive(Debug)]
 pub struct OutValue {
-    pub target: String,
+    pub filter_id: String,
+    // id нод, групп нод (v-s:LinkedNodeGroup) или "*"
+    pub targets: Vec<String>,
     pub indv: Option<Individual>,
     pub enable_scripts: bool,
 }
 
+// параметры выгрузки, заданные в индивиде v-s:EximFilter
+#[derive(Debug, Default, Clone)]
+pub struct FilterOptions {
+    // выгружать только измененные значения предикатов (v-s:exportDelta)
+    pub export_delta: bool,
+    // предикаты, по ссылкам которых выгружаются связанные индивиды (v-s:exportFollowPredicate)
+    pub follow_predicates: Vec<String>,
+    // глубина обхода ссылок (v-s:exportFollowDepth)
+    pub follow_depth: i64,
+    // состав выгружаемых предикатов и маскирование значений
+    pub projection: Projection,
+}
+
+impl FilterOptions {
+    fn new(ev_indv: &mut Individual) -> Self {
+        let follow_predicates = ev_indv.get_literals("v-s:exportFollowPredicate").unwrap_or_default();
+        let follow_depth = if follow_predicates.is_empty() {
+            0
+        } else {
+            ev_indv.get_first_integer("v-s:exportFollowDepth").unwrap_or(1)
+        };
+
+        FilterOptions {
+            export_delta: ev_indv.get_first_bool("v-s:exportDelta").unwrap_or(false),
+            follow_predicates,
+            follow_depth,
+            projection: Projection::new(ev_indv),
+        }
+    }
+}
+
 pub fn is_exportable(
     backend: &mut Backend,
     ctx: &mut Context,
@@ -92,19 +126,20 @@ pub fn is_exportable(
                                 for resources_idx in 0..key_list.length() {
                                     let j_resources_idx = v8::Integer::new(&mut scope, resources_idx as i32);
                                     if let Some(v) = res.get(&mut scope, j_resources_idx.into()) {
-                                        prepare_out_obj(backend, &mut ov, v, &mut scope);
+                                        prepare_out_obj(backend, &mut ov, script_id, v, &mut scope);
                                     }
                                 }
                             }
                         }
                     } else if res.is_object() {
-                        prepare_out_obj(backend, &mut ov, res, &mut scope);
+                        prepare_out_obj(backend, &mut ov, script_id, res, &mut scope);
                     } else if res.is_string() {
                         if let Some(s) = res.to_string(scope.as_mut()) {
                             let target = s.to_rust_string_lossy(&mut scope);
                             if !target.is_empty() {
                                 ov.push(OutValue {
-                                    target,
+                                    filter_id: script_id.to_owned(),
+                                    targets: vec![target],
                                     indv: None,
                                     enable_scripts: false,
                                 });
@@ -122,20 +157,33 @@ pub fn is_exportable(
     ov
 }
 
-fn prepare_out_obj(backend: &mut Backend, ov: &mut Vec<OutValue>, res: Local<Value>, scope: &mut ContextScope<HandleScope>) {
+fn prepare_out_obj(backend: &mut Backend, ov: &mut Vec<OutValue>, filter_id: &str, res: Local<Value>, scope: &mut ContextScope<HandleScope>) {
     if let Some(out_obj) = res.to_object(scope) {
         let to_key = str_2_v8(scope, "to");
         let indv_key = str_2_v8(scope, "indv");
         let indv_id_key = str_2_v8(scope, "indv_id");
         let enable_scripts_key = str_2_v8(scope, "enable_scripts");
 
-        let mut target = String::new();
+        let mut targets = vec![];
 
+        // 'to' может содержать одну цель либо массив целей
         if let Some(v_to) = out_obj.get(scope, to_key.into()) {
-            if let Some(v) = v_to.to_string(scope) {
-                target = v.to_rust_string_lossy(scope);
+            if v_to.is_array() {
+                if let Some(to_list) = v_to.to_object(scope) {
+                    if let Some(key_list) = to_list.get_property_names(scope) {
+                        for idx in 0..key_list.length() {
+                            let j_idx = v8::Integer::new(scope, idx as i32);
+                            if let Some(v) = to_list.get(scope, j_idx.into()).and_then(|v| v.to_string(scope)) {
+                                targets.push(v.to_rust_string_lossy(scope));
+                            }
+                        }
+                    }
+                }
+            } else if let Some(v) = v_to.to_string(scope) {
+                targets.push(v.to_rust_string_lossy(scope));
             }
         }
+        targets.retain(|t| !t.is_empty());
 
         let mut indv = None;
 
@@ -169,27 +217,28 @@ fn prepare_out_obj(backend: &mut Backend, ov: &mut Vec<OutValue>, res: Local<Val
         }
 
         ov.push(OutValue {
-            target,
+            filter_id: filter_id.to_owned(),
+            targets,
             indv,
             enable_scripts,
         });
     }
 }
 
-pub(crate) fn load_exim_filter_scripts(wp: &mut ScriptsWorkPlace<ScriptInfoContext>, xr: &mut XapianReader) {
+pub(crate) fn load_exim_filter_scripts(wp: &mut ScriptsWorkPlace<ScriptInfoContext>, xr: &mut XapianReader, filters: &mut HashMap<String, FilterOptions>) {
     let res = xr.query(FTQuery::new_with_user("cfg:VedaSystem", "'rdf:type' === 'v-s:EximFilter'"), &mut wp.backend.storage);
 
     if res.result_code == ResultCode::Ok && res.count > 0 {
         for id in &res.result {
             if let Some(ev_indv) = wp.backend.get_individual(id, &mut Individual::default()) {
-                prepare_script(wp, ev_indv);
+                prepare_script(wp, ev_indv, filters);
             }
         }
     }
     info!("load scripts from db: {:?}", wp.scripts_order);
 }
 
-pub(crate) fn prepare_script(wp: &mut ScriptsWorkPlace<ScriptInfoContext>, ev_indv: &mut Individual) {
+pub(crate) fn prepare_script(wp: &mut ScriptsWorkPlace<ScriptInfoContext>, ev_indv: &mut Individual, filters: &mut HashMap<String, FilterOptions>) {
     if ev_indv.is_exists_bool("v-s:deleted", true) || ev_indv.is_exists_bool("v-s:disabled", true) {
         info!("disable script {}", ev_indv.get_id());
         if let Some(scr_inf) = wp.scripts.get_mut(ev_indv.get_id()) {
@@ -226,6 +275,8 @@ pub(crate) fn prepare_script(wp: &mut ScriptsWorkPlace<ScriptInfoContext>, ev_in
         let scope = &mut v8::ContextScope::new(&mut wp.scope, wp.context);
         scr_inf.compile_script(ev_indv.get_id(), scope);
         wp.scripts.insert(scr_inf.id.to_string(), scr_inf);
+
+        filters.insert(ev_indv.get_id().to_owned(), FilterOptions::new(ev_indv));
     } else {
         error!("v-s:script no found");
     }